@@ -5,7 +5,7 @@ fn main() {
         .with_module_level_filter("explicit".into(), log::LevelFilter::Trace)
         .with_explicit_logging();
 
-    tacit::new().with_logger(json_logger).log().unwrap();
+    let _guard = tacit::new().with_logger(json_logger).log().unwrap();
     log::info!("logging a thing");
     log::debug!("something to debug");
     log::trace!("something to trace");
@@ -9,7 +9,7 @@ fn main() {
         .with_module_level_filter("want".into(), log::LevelFilter::Off)
         .with_level_filter(log::LevelFilter::Trace);
 
-    tacit::new().with_logger(json_logger).log().unwrap();
+    let _guard = tacit::new().with_logger(json_logger).log().unwrap();
     log::info!("logging a thing");
     log::debug!("something to debug");
     log::trace!("something to trace");
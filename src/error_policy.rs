@@ -0,0 +1,169 @@
+//! # Error Policy
+//! Decides what happens when an entry can not be written to its output, such as
+//! when stdout is a closed pipe or the disk is full. Logging never panics because
+//! of a failed write.
+
+use parking_lot::Mutex;
+use std::{
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// What a `Logger` does when writing to its output fails.
+#[derive(Default)]
+pub enum ErrorPolicy {
+    /// Silently discard the entry.
+    Ignore,
+    /// Print the first failure to stderr, then silently discard entries.
+    #[default]
+    ReportOnce,
+    /// Call a function with every failure.
+    Callback(Box<dyn Fn(&io::Error) + Send + Sync>),
+    /// Write the entry to another writer instead, e.g. `std::io::stderr()`.
+    Fallback(Mutex<Box<dyn Write + Send>>),
+}
+
+impl ErrorPolicy {
+    /// Call `callback` with every failure.
+    pub fn callback<C: Fn(&io::Error) + Send + Sync + 'static>(callback: C) -> Self {
+        Self::Callback(Box::new(callback))
+    }
+
+    /// Write entries that failed to `writer` instead.
+    pub fn fallback<W: Write + Send + 'static>(writer: W) -> Self {
+        Self::Fallback(Mutex::new(Box::new(writer)))
+    }
+}
+
+/// Applies an `ErrorPolicy`, tracking whether a failure has been reported yet.
+#[derive(Default)]
+pub(crate) struct ErrorHandler {
+    policy: ErrorPolicy,
+    reported: AtomicBool,
+}
+
+impl ErrorHandler {
+    pub(crate) fn new(policy: ErrorPolicy) -> Self {
+        Self {
+            policy,
+            reported: AtomicBool::new(false),
+        }
+    }
+
+    /// Handle a failure writing `entry`, or flushing when there is no entry.
+    pub(crate) fn handle(&self, err: &io::Error, entry: Option<&[u8]>) {
+        match &self.policy {
+            ErrorPolicy::Ignore => {}
+            ErrorPolicy::ReportOnce => {
+                if !self.reported.swap(true, Ordering::Relaxed) {
+                    eprintln!("tacit: unable to write to logger output: {}", err);
+                }
+            }
+            ErrorPolicy::Callback(callback) => callback(err),
+            ErrorPolicy::Fallback(writer) => {
+                if let Some(entry) = entry {
+                    let _ = writer.lock().write_all(entry);
+                }
+            }
+        }
+    }
+}
+
+/// Hands failures an output runs into away from `write_entry`, e.g. on a background
+/// thread, to the `ErrorPolicy` of the logger it belongs to.
+#[derive(Clone, Default)]
+pub struct ErrorReporter(Arc<ErrorHandler>);
+
+impl ErrorReporter {
+    pub(crate) fn new(errors: Arc<ErrorHandler>) -> Self {
+        Self(errors)
+    }
+
+    /// Report a failure, along with the entries that were lost because of it, if any.
+    pub fn report(&self, err: &io::Error, entries: Option<&[u8]>) {
+        self.0.handle(err, entries);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util::SharedOutput, Logger, SimpleFormatter, TacitOutput};
+    use log::{Log, Record};
+    use std::sync::atomic::AtomicUsize;
+
+    #[derive(Default)]
+    struct BrokenPipeOutput;
+
+    impl TacitOutput for BrokenPipeOutput {}
+
+    impl Write for BrokenPipeOutput {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn log_entry<L: Log>(logger: &L) {
+        logger.log(
+            &Record::builder()
+                .args(format_args!("lost"))
+                .level(log::Level::Error)
+                .build(),
+        );
+    }
+
+    #[test]
+    fn calls_back_on_failure() {
+        let failures = Arc::new(AtomicUsize::new(0));
+        let logger = Logger::new(BrokenPipeOutput, SimpleFormatter::default())
+            .with_error_policy(ErrorPolicy::callback({
+                let failures = failures.clone();
+                move |err| {
+                    assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+                    failures.fetch_add(1, Ordering::Relaxed);
+                }
+            }))
+            .finalize();
+
+        log_entry(&logger);
+        log_entry(&logger);
+
+        assert_eq!(failures.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn falls_back_to_other_writer() {
+        let fallback = SharedOutput::default();
+        let logger = Logger::new(BrokenPipeOutput, SimpleFormatter::default())
+            .with_error_policy(ErrorPolicy::fallback(fallback.clone()))
+            .finalize();
+
+        log_entry(&logger);
+
+        assert_eq!(fallback.contents(), "msg=lost\n");
+    }
+
+    #[cfg(feature = "threaded")]
+    #[test]
+    fn falls_back_from_writer_thread() {
+        use crate::TacitLog;
+
+        let fallback = SharedOutput::default();
+        let logger = Logger::new(BrokenPipeOutput, SimpleFormatter::default())
+            .with_error_policy(ErrorPolicy::fallback(fallback.clone()))
+            .with_async_writes(4)
+            .finalize();
+
+        log_entry(&logger);
+        logger.shutdown();
+
+        assert_eq!(fallback.contents(), "msg=lost\n");
+    }
+}
@@ -0,0 +1,270 @@
+//! # CEF Formatter
+//! Structures log output in ArcSight's Common Event Format.
+//!
+//! ```sh
+//! CEF:0|tacit|tacit|0.1.2|my_app::module|logging a thing|5|level=INFO timeStamp=2021-03-29T15:49:16.425441203+00:00
+//! ```
+//!
+//! The record's message becomes the event name, and its target the signature id
+//! unless a fixed one is configured. Properties and key value pairs are written
+//! as extensions, using their own names unless mapped to a CEF key.
+
+use crate::{formatters::TacitFormatter, Property, StaticProperty};
+use log::{Level, Record};
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+pub struct CefFormatter {
+    vendor: String,
+    product: String,
+    version: String,
+    signature_id: Option<String>,
+    extension_keys: HashMap<String, String>,
+}
+
+impl Default for CefFormatter {
+    fn default() -> Self {
+        Self::new("tacit", "tacit", env!("CARGO_PKG_VERSION"))
+    }
+}
+
+impl CefFormatter {
+    /// Create a formatter reporting events from the given device vendor, product and version.
+    pub fn new<V: Into<String>, P: Into<String>, R: Into<String>>(
+        vendor: V,
+        product: P,
+        version: R,
+    ) -> Self {
+        Self {
+            vendor: vendor.into(),
+            product: product.into(),
+            version: version.into(),
+            signature_id: None,
+            extension_keys: HashMap::new(),
+        }
+    }
+
+    /// Use a fixed signature id for every event, instead of the record's target.
+    pub fn set_signature_id<S: Into<String>>(&mut self, signature_id: S) {
+        self.signature_id = Some(signature_id.into());
+    }
+
+    /// Use a fixed signature id for every event, instead of the record's target. Useful for
+    /// chaining operations.
+    #[must_use]
+    pub fn with_signature_id<S: Into<String>>(mut self, signature_id: S) -> Self {
+        self.set_signature_id(signature_id);
+        self
+    }
+
+    /// Write the property or key value pair `name` with the CEF extension key `key`, e.g.
+    /// `timeStamp` as `rt`.
+    pub fn map_extension_key<N: Into<String>, K: Into<String>>(&mut self, name: N, key: K) {
+        self.extension_keys.insert(name.into(), key.into());
+    }
+
+    /// Write the property or key value pair `name` with the CEF extension key `key`, e.g.
+    /// `timeStamp` as `rt`. Useful for chaining operations.
+    #[must_use]
+    pub fn with_extension_key<N: Into<String>, K: Into<String>>(mut self, name: N, key: K) -> Self {
+        self.map_extension_key(name, key);
+        self
+    }
+
+    /// CEF extension key for a property name, keys may only contain alphanumeric characters.
+    fn extension_key(&self, name: &str) -> String {
+        match self.extension_keys.get(name) {
+            Some(key) => key.clone(),
+            None => name.chars().filter(|c| c.is_ascii_alphanumeric()).collect(),
+        }
+    }
+
+    /// Write a `key=value` pair, separated from the previous one unless it is the `first`.
+    fn write_extension(
+        &self,
+        buf: &mut Vec<u8>,
+        first: &mut bool,
+        name: &str,
+        value: &str,
+    ) -> io::Result<()> {
+        let key = self.extension_key(name);
+        if key.is_empty() {
+            return Ok(());
+        }
+
+        if !*first {
+            buf.push(b' ');
+        }
+        *first = false;
+        write!(buf, "{}={}", key, escape_extension(value))
+    }
+}
+
+/// CEF severity, from 0 to 10, for a log level.
+pub fn cef_severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 9,
+        Level::Warn => 7,
+        Level::Info => 5,
+        Level::Debug => 3,
+        Level::Trace => 1,
+    }
+}
+
+/// Escape a header field, pipes and backslashes are escaped and line breaks are not allowed.
+pub(crate) fn escape_header(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '|' => escaped.push_str("\\|"),
+            '\r' | '\n' => escaped.push(' '),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escape an extension value, equals signs, backslashes and line breaks are escaped.
+fn escape_extension(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '=' => escaped.push_str("\\="),
+            '\r' => escaped.push_str("\\r"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl TacitFormatter for CefFormatter {
+    fn format(
+        &self,
+        buf: &mut Vec<u8>,
+        record: &Record,
+        _msg_prop: &str,
+        default_props: &[(String, Property)],
+        ignore_empty_props: bool,
+    ) -> io::Result<()> {
+        let name = format!("{}", record.args());
+        let signature_id = self.signature_id.as_deref().unwrap_or(record.target());
+
+        write!(
+            buf,
+            "CEF:0|{}|{}|{}|{}|{}|{}|",
+            escape_header(&self.vendor),
+            escape_header(&self.product),
+            escape_header(&self.version),
+            escape_header(signature_id),
+            escape_header(&name),
+            cef_severity(record.level()),
+        )?;
+
+        let mut first = true;
+        for prop in default_props {
+            match prop.1.cef_value(record) {
+                Some(value) => self.write_extension(buf, &mut first, &prop.0, &value)?,
+                None if ignore_empty_props => {}
+                None => self.write_extension(buf, &mut first, &prop.0, "")?,
+            }
+        }
+
+        #[cfg(feature = "kv")]
+        for (key, value) in super::kv_strings(record) {
+            self.write_extension(buf, &mut first, &key, &value)?;
+        }
+
+        writeln!(buf)
+    }
+}
+
+impl StaticProperty {
+    pub fn cef_value(&self) -> Option<String> {
+        match self {
+            Self::String(v) => Some(v.to_string()),
+            Self::Number(v) => Some(v.to_string()),
+            Self::Null => None,
+        }
+    }
+}
+
+impl Property {
+    pub fn cef_value(&self, record: &Record) -> Option<String> {
+        match self {
+            Self::Static(prop) => prop.cef_value(),
+            Self::Function(f) => f(record).cef_value(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(formatter: &CefFormatter, props: &[(String, Property)], message: &str) -> String {
+        let mut buf = Vec::new();
+        formatter
+            .format(
+                &mut buf,
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(Level::Warn)
+                    .target("my_app::net")
+                    .build(),
+                "msg",
+                props,
+                true,
+            )
+            .unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn renders_header_and_extensions() {
+        let formatter =
+            CefFormatter::new("Acme", "Widget", "1.0").with_extension_key("host", "dhost");
+        let props = vec![
+            (String::from("host"), Property::Static("web-1".into())),
+            (String::from("pid"), Property::Static(42i64.into())),
+            (
+                String::from("empty"),
+                Property::Static(StaticProperty::Null),
+            ),
+        ];
+
+        assert_eq!(
+            format(&formatter, &props, "connection lost"),
+            "CEF:0|Acme|Widget|1.0|my_app::net|connection lost|7|dhost=web-1 pid=42\n"
+        );
+    }
+
+    #[test]
+    fn escapes_fields() {
+        let formatter = CefFormatter::new("Ac|me", "Wid\\get", "1.0").with_signature_id("100");
+        let props = vec![(String::from("query"), Property::Static("a=b\\c\nd".into()))];
+
+        assert_eq!(
+            format(&formatter, &props, "pipe | line\nbreak"),
+            "CEF:0|Ac\\|me|Wid\\\\get|1.0|100|pipe \\| line break|7|query=a\\=b\\\\c\\nd\n"
+        );
+    }
+
+    #[test]
+    fn separates_extensions_after_values_ending_in_a_pipe() {
+        let formatter = CefFormatter::new("Acme", "Widget", "1.0");
+        let props = vec![
+            (String::from("cmd"), Property::Static("ls |".into())),
+            (String::from("pid"), Property::Static(42i64.into())),
+        ];
+
+        assert_eq!(
+            format(&formatter, &props, "ran"),
+            "CEF:0|Acme|Widget|1.0|my_app::net|ran|7|cmd=ls | pid=42\n"
+        );
+    }
+}
@@ -0,0 +1,129 @@
+//! # Color
+//! ANSI styling for formatters writing to a terminal. Levels are colored, and keys
+//! can be dimmed to set them apart from values.
+//!
+//! `ColorChoice::Auto` is resolved against the stream entries are written to, which
+//! the output reports once the formatter belongs to a logger. Outputs routing
+//! entries by level, such as `SplitConsoleOutput`, are resolved per level.
+
+use log::Level;
+use std::{env, ffi::OsString};
+
+pub(crate) const RESET: &str = "\x1b[0m";
+pub(crate) const KEY_STYLE: &str = "\x1b[2m";
+
+/// When a formatter should style its output with ANSI colors.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Color when the output writes to a terminal. `NO_COLOR` disables colors,
+    /// otherwise `CLICOLOR_FORCE` enables them even when it does not.
+    Auto,
+    /// Always color.
+    Always,
+    /// Never color.
+    #[default]
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether output written to a stream should be colored, resolving `Auto` against
+    /// the environment and whether the stream is a terminal.
+    pub fn enabled(self, is_terminal: bool) -> bool {
+        match self {
+            Self::Auto => auto_enabled(
+                env::var_os("NO_COLOR"),
+                env::var_os("CLICOLOR_FORCE"),
+                is_terminal,
+            ),
+            Self::Always => true,
+            Self::Never => false,
+        }
+    }
+}
+
+fn auto_enabled(no_color: Option<OsString>, force: Option<OsString>, is_terminal: bool) -> bool {
+    if no_color.is_some_and(|v| !v.is_empty()) {
+        false
+    } else if force.is_some_and(|v| !v.is_empty() && v != "0") {
+        true
+    } else {
+        is_terminal
+    }
+}
+
+/// A `ColorChoice` resolved for each level, as entries at different levels may be written
+/// to different streams.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Colors {
+    choice: ColorChoice,
+    levels: [bool; 5],
+}
+
+impl Colors {
+    /// Resolve `choice` as if no stream were a terminal, until told otherwise.
+    pub(crate) fn new(choice: ColorChoice) -> Self {
+        Self {
+            choice,
+            levels: [choice.enabled(false); 5],
+        }
+    }
+
+    /// Resolve the choice again, for the streams entries at each level are written to.
+    pub(crate) fn set_terminal(&mut self, is_terminal: &dyn Fn(Level) -> bool) {
+        for level in Level::iter() {
+            self.levels[level as usize - 1] = self.choice.enabled(is_terminal(level));
+        }
+    }
+
+    /// Whether entries at `level` are colored.
+    pub(crate) fn enabled(&self, level: Level) -> bool {
+        self.levels[level as usize - 1]
+    }
+}
+
+/// ANSI style for a log level.
+pub(crate) fn level_style(level: Level) -> &'static str {
+    match level {
+        Level::Error => "\x1b[31m",
+        Level::Warn => "\x1b[33m",
+        Level::Info => "\x1b[32m",
+        Level::Debug => "\x1b[34m",
+        Level::Trace => "\x1b[35m",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_auto_from_environment() {
+        let set = |v: &str| Some(OsString::from(v));
+
+        assert!(auto_enabled(None, None, true));
+        assert!(!auto_enabled(None, None, false));
+        assert!(!auto_enabled(set("1"), None, true));
+        assert!(!auto_enabled(set("1"), set("1"), true));
+        assert!(auto_enabled(set(""), None, true));
+        assert!(auto_enabled(None, set("1"), false));
+        assert!(!auto_enabled(None, set("0"), false));
+    }
+
+    #[test]
+    fn resolves_each_level_for_its_stream() {
+        // the outcome of `Auto` depends on the environment otherwise
+        if env::var_os("NO_COLOR").is_none() && env::var_os("CLICOLOR_FORCE").is_none() {
+            let mut colors = Colors::new(ColorChoice::Auto);
+            assert!(Level::iter().all(|level| !colors.enabled(level)));
+
+            colors.set_terminal(&|level| level <= Level::Warn);
+            assert!(colors.enabled(Level::Error));
+            assert!(colors.enabled(Level::Warn));
+            assert!(!colors.enabled(Level::Info));
+        }
+
+        let mut colors = Colors::new(ColorChoice::Always);
+        colors.set_terminal(&|_| false);
+        assert!(Level::iter().all(|level| colors.enabled(level)));
+    }
+}
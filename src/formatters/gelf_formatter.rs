@@ -0,0 +1,176 @@
+//! # Gelf Formatter
+//! Structures log output as GELF 1.1 messages, as understood by Graylog.
+//!
+//! ```json
+//! {"version":"1.1","host":"web-1","short_message":"logging a thing","timestamp":1617032956.425,"level":6,"_request":"42"}
+//! ```
+//!
+//! The message property becomes `short_message`, the record's level its syslog
+//! severity, and every other property an additional field prefixed with `_`. Null
+//! properties are left out, GELF fields are strings or numbers.
+
+use crate::{formatters::TacitFormatter, outputs::hostname, syslog_severity, Property};
+use chrono::Utc;
+use log::Record;
+use serde_json::{json, Map, Value};
+use std::io::{self, Write};
+
+pub struct GelfFormatter {
+    host: String,
+}
+
+impl Default for GelfFormatter {
+    fn default() -> Self {
+        Self { host: hostname() }
+    }
+}
+
+impl GelfFormatter {
+    /// Set the `host` of every message, the system's host name by default.
+    pub fn set_host<S: Into<String>>(&mut self, host: S) {
+        self.host = host.into();
+    }
+
+    /// Set the `host` of every message, the system's host name by default. Useful for
+    /// chaining operations.
+    #[must_use]
+    pub fn with_host<S: Into<String>>(mut self, host: S) -> Self {
+        self.set_host(host);
+        self
+    }
+}
+
+/// Name of the additional field for `name`. Characters GELF does not allow are replaced
+/// with `_`, and `id`, reserved as `_id`, becomes `__id`.
+fn field_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if name == "id" {
+        String::from("__id")
+    } else {
+        format!("_{}", name)
+    }
+}
+
+/// GELF values are strings or numbers, anything else is rendered as a string or dropped.
+fn field_value(value: Value) -> Option<Value> {
+    match value {
+        Value::Null => None,
+        Value::String(_) | Value::Number(_) => Some(value),
+        other => Some(Value::String(other.to_string())),
+    }
+}
+
+impl TacitFormatter for GelfFormatter {
+    fn format(
+        &self,
+        buf: &mut Vec<u8>,
+        record: &Record,
+        msg_prop: &str,
+        default_props: &[(String, Property)],
+        _ignore_empty_props: bool,
+    ) -> io::Result<()> {
+        let now = Utc::now();
+        let timestamp = now.timestamp() as f64 + f64::from(now.timestamp_subsec_millis()) / 1000.0;
+
+        let mut item = Map::new();
+        item.insert(String::from("version"), json!("1.1"));
+        item.insert(String::from("host"), json!(self.host));
+        item.insert(
+            String::from("short_message"),
+            json!(format!("{}", record.args())),
+        );
+        item.insert(String::from("timestamp"), json!(timestamp));
+        item.insert(
+            String::from("level"),
+            json!(syslog_severity(record.level())),
+        );
+
+        for (name, prop) in default_props {
+            // the record's level and message have fields of their own
+            if name == "level" || name == msg_prop {
+                continue;
+            }
+            if let Some(value) = field_value(prop.json_value(record)) {
+                item.insert(field_name(name), value);
+            }
+        }
+
+        #[cfg(feature = "kv")]
+        {
+            let mut visitor = super::KvVisitor::new(json!({}));
+            let _ = record.key_values().visit(&mut visitor);
+            if let Value::Object(pairs) = visitor.inner() {
+                for (key, value) in pairs {
+                    if let Some(value) = field_value(value) {
+                        item.insert(field_name(&key), value);
+                    }
+                }
+            }
+        }
+
+        serde_json::to_writer(&mut *buf, &item)?;
+        writeln!(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StaticProperty;
+    use log::Level;
+
+    #[test]
+    fn maps_properties_to_gelf_fields() {
+        let formatter = GelfFormatter::default().with_host("web-1");
+        let props = vec![
+            (
+                String::from("level"),
+                Property::Function(Box::new(|rec: &Record| rec.level().to_string().into())),
+            ),
+            (String::from("msg"), Property::Static("shadowed".into())),
+            (String::from("request id"), Property::Static("42".into())),
+            (
+                String::from("id"),
+                Property::Static(StaticProperty::Number(7)),
+            ),
+            (String::from("none"), Property::Static(StaticProperty::Null)),
+        ];
+
+        let mut buf = Vec::new();
+        formatter
+            .format(
+                &mut buf,
+                &Record::builder()
+                    .args(format_args!("disk full"))
+                    .level(Level::Warn)
+                    .target("my_app")
+                    .build(),
+                "msg",
+                &props,
+                false,
+            )
+            .unwrap();
+
+        assert_eq!(buf.last(), Some(&b'\n'));
+        let value: Value = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(value["version"], "1.1");
+        assert_eq!(value["host"], "web-1");
+        assert_eq!(value["short_message"], "disk full");
+        assert_eq!(value["level"], 4);
+        assert!(value["timestamp"].as_f64().unwrap() > 1_600_000_000.0);
+        assert_eq!(value["_request_id"], "42");
+        assert_eq!(value["__id"], 7);
+        for absent in &["_level", "_msg", "_id", "_none"] {
+            assert!(value.get(absent).is_none(), "{} is set", absent);
+        }
+    }
+}
@@ -5,24 +5,23 @@
 //! {"level":"INFO","msg":"logging a thing","timeStamp":"2021-03-29T15:49:16.425441203+00:00"}
 //! ```
 
-use crate::{formatters::TacitFormatter, Property, StaticProperty, TacitOutput};
+use crate::{formatters::TacitFormatter, Property, StaticProperty};
 use log::Record;
 use serde_json::{json, Value};
+use std::io::{self, Write};
 
 #[derive(Default)]
 pub struct JsonFormatter {}
 
 impl TacitFormatter for JsonFormatter {
-    fn log<O>(
+    fn format(
         &self,
-        output: &mut O,
+        buf: &mut Vec<u8>,
         record: &Record,
         msg_prop: &str,
         default_props: &[(String, Property)],
         ignore_empty_props: bool,
-    ) where
-        O: TacitOutput,
-    {
+    ) -> io::Result<()> {
         let line = format!("{}", record.args());
 
         let mut item = json!({ msg_prop: line });
@@ -49,12 +48,8 @@ impl TacitFormatter for JsonFormatter {
             item = visitor.inner();
         }
 
-        writeln!(
-            output,
-            "{}",
-            serde_json::to_string(&item).expect("Generated invalid JSON object during logging")
-        )
-        .expect("Unable to write to logger output");
+        serde_json::to_writer(&mut *buf, &item)?;
+        writeln!(buf)
     }
 }
 
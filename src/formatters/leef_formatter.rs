@@ -0,0 +1,326 @@
+//! # LEEF Formatter
+//! Structures log output in IBM QRadar's Log Event Extended Format, version 1.0
+//! or 2.0.
+//!
+//! ```sh
+//! LEEF:2.0|tacit|tacit|0.1.2|my_app::module|^|sev=5^level=INFO^msg=logging a thing
+//! ```
+//!
+//! The record's target becomes the event id unless a fixed one is configured.
+//! The severity, properties, message and key value pairs are written as
+//! attributes, using their own names unless mapped to a LEEF attribute key.
+//! Version 1.0 always separates attributes with a tab, version 2.0 can use a
+//! custom delimiter.
+
+use super::cef_formatter::escape_header;
+use crate::{cef_severity, formatters::TacitFormatter, Property, StaticProperty};
+use log::Record;
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+/// Version of the LEEF specification to produce.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LeefVersion {
+    V1,
+    V2,
+}
+
+pub struct LeefFormatter {
+    version: LeefVersion,
+    delimiter: char,
+    vendor: String,
+    product: String,
+    product_version: String,
+    event_id: Option<String>,
+    attribute_keys: HashMap<String, String>,
+}
+
+impl Default for LeefFormatter {
+    fn default() -> Self {
+        Self::new("tacit", "tacit", env!("CARGO_PKG_VERSION"))
+    }
+}
+
+impl LeefFormatter {
+    /// Create a LEEF 2.0 formatter reporting events from the given vendor, product and
+    /// product version.
+    pub fn new<V: Into<String>, P: Into<String>, R: Into<String>>(
+        vendor: V,
+        product: P,
+        product_version: R,
+    ) -> Self {
+        Self {
+            version: LeefVersion::V2,
+            delimiter: '\t',
+            vendor: vendor.into(),
+            product: product.into(),
+            product_version: product_version.into(),
+            event_id: None,
+            attribute_keys: HashMap::new(),
+        }
+    }
+
+    /// Set the version of the LEEF specification to produce.
+    pub fn set_version(&mut self, version: LeefVersion) {
+        self.version = version;
+    }
+
+    /// Set the version of the LEEF specification to produce. Useful for chaining operations.
+    #[must_use]
+    pub fn with_version(mut self, version: LeefVersion) -> Self {
+        self.set_version(version);
+        self
+    }
+
+    /// Separate attributes with `delimiter` instead of a tab. Only LEEF 2.0 supports custom
+    /// delimiters, LEEF 1.0 always uses a tab.
+    pub fn set_delimiter(&mut self, delimiter: char) {
+        self.delimiter = delimiter;
+    }
+
+    /// Separate attributes with `delimiter` instead of a tab. Only LEEF 2.0 supports custom
+    /// delimiters, LEEF 1.0 always uses a tab. Useful for chaining operations.
+    #[must_use]
+    pub fn with_delimiter(mut self, delimiter: char) -> Self {
+        self.set_delimiter(delimiter);
+        self
+    }
+
+    /// Use a fixed event id for every event, instead of the record's target.
+    pub fn set_event_id<S: Into<String>>(&mut self, event_id: S) {
+        self.event_id = Some(event_id.into());
+    }
+
+    /// Use a fixed event id for every event, instead of the record's target. Useful for
+    /// chaining operations.
+    #[must_use]
+    pub fn with_event_id<S: Into<String>>(mut self, event_id: S) -> Self {
+        self.set_event_id(event_id);
+        self
+    }
+
+    /// Write the property or key value pair `name` with the LEEF attribute key `key`, e.g.
+    /// `host` as `src`.
+    pub fn map_attribute_key<N: Into<String>, K: Into<String>>(&mut self, name: N, key: K) {
+        self.attribute_keys.insert(name.into(), key.into());
+    }
+
+    /// Write the property or key value pair `name` with the LEEF attribute key `key`, e.g.
+    /// `host` as `src`. Useful for chaining operations.
+    #[must_use]
+    pub fn with_attribute_key<N: Into<String>, K: Into<String>>(mut self, name: N, key: K) -> Self {
+        self.map_attribute_key(name, key);
+        self
+    }
+
+    fn delimiter(&self) -> char {
+        match self.version {
+            LeefVersion::V1 => '\t',
+            LeefVersion::V2 => self.delimiter,
+        }
+    }
+
+    /// LEEF attribute key for a property name, without whitespace, `=` or the delimiter.
+    fn attribute_key(&self, name: &str) -> String {
+        match self.attribute_keys.get(name) {
+            Some(key) => key.clone(),
+            None => name
+                .chars()
+                .filter(|c| !c.is_whitespace() && *c != '=' && *c != self.delimiter())
+                .collect(),
+        }
+    }
+
+    /// Write a `key=value` attribute, delimited from the previous one unless it is the `first`.
+    fn write_attribute(
+        &self,
+        buf: &mut Vec<u8>,
+        first: &mut bool,
+        name: &str,
+        value: &str,
+    ) -> io::Result<()> {
+        let key = self.attribute_key(name);
+        if key.is_empty() {
+            return Ok(());
+        }
+
+        if !*first {
+            write!(buf, "{}", self.delimiter())?;
+        }
+        *first = false;
+        write!(buf, "{}={}", key, self.escape_value(value))
+    }
+
+    /// Escape an attribute value, backslashes, the delimiter and line breaks are escaped.
+    fn escape_value(&self, value: &str) -> String {
+        let delimiter = self.delimiter();
+        let mut escaped = String::with_capacity(value.len());
+        for c in value.chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                '\r' => escaped.push_str("\\r"),
+                '\n' => escaped.push_str("\\n"),
+                c if c == delimiter => {
+                    escaped.push('\\');
+                    escaped.push(c);
+                }
+                c => escaped.push(c),
+            }
+        }
+        escaped
+    }
+
+    /// Delimiter as written in the LEEF 2.0 header, control characters use their hex form.
+    fn header_delimiter(&self) -> String {
+        if self.delimiter.is_ascii_graphic() && self.delimiter != '|' {
+            self.delimiter.to_string()
+        } else {
+            format!("x{:02X}", self.delimiter as u32)
+        }
+    }
+}
+
+impl TacitFormatter for LeefFormatter {
+    fn format(
+        &self,
+        buf: &mut Vec<u8>,
+        record: &Record,
+        msg_prop: &str,
+        default_props: &[(String, Property)],
+        ignore_empty_props: bool,
+    ) -> io::Result<()> {
+        let event_id = self.event_id.as_deref().unwrap_or(record.target());
+        let version = match self.version {
+            LeefVersion::V1 => "1.0",
+            LeefVersion::V2 => "2.0",
+        };
+
+        write!(
+            buf,
+            "LEEF:{}|{}|{}|{}|{}|",
+            version,
+            escape_header(&self.vendor),
+            escape_header(&self.product),
+            escape_header(&self.product_version),
+            escape_header(event_id),
+        )?;
+        if self.version == LeefVersion::V2 {
+            write!(buf, "{}|", self.header_delimiter())?;
+        }
+
+        let mut first = true;
+        self.write_attribute(
+            buf,
+            &mut first,
+            "sev",
+            &cef_severity(record.level()).to_string(),
+        )?;
+
+        for prop in default_props {
+            match prop.1.leef_value(record) {
+                Some(value) => self.write_attribute(buf, &mut first, &prop.0, &value)?,
+                None if ignore_empty_props => {}
+                None => self.write_attribute(buf, &mut first, &prop.0, "")?,
+            }
+        }
+
+        self.write_attribute(buf, &mut first, msg_prop, &format!("{}", record.args()))?;
+
+        #[cfg(feature = "kv")]
+        for (key, value) in super::kv_strings(record) {
+            self.write_attribute(buf, &mut first, &key, &value)?;
+        }
+
+        writeln!(buf)
+    }
+}
+
+impl StaticProperty {
+    pub fn leef_value(&self) -> Option<String> {
+        match self {
+            Self::String(v) => Some(v.to_string()),
+            Self::Number(v) => Some(v.to_string()),
+            Self::Null => None,
+        }
+    }
+}
+
+impl Property {
+    pub fn leef_value(&self, record: &Record) -> Option<String> {
+        match self {
+            Self::Static(prop) => prop.leef_value(),
+            Self::Function(f) => f(record).leef_value(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    fn format(formatter: &LeefFormatter, props: &[(String, Property)], message: &str) -> String {
+        let mut buf = Vec::new();
+        formatter
+            .format(
+                &mut buf,
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(Level::Error)
+                    .target("my_app::net")
+                    .build(),
+                "msg",
+                props,
+                true,
+            )
+            .unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn renders_leef_1() {
+        let formatter = LeefFormatter::new("Acme", "Widget", "1.0")
+            .with_version(LeefVersion::V1)
+            .with_delimiter('^')
+            .with_attribute_key("host", "src");
+        let props = vec![(String::from("host"), Property::Static("10.0.0.1".into()))];
+
+        assert_eq!(
+            format(&formatter, &props, "denied"),
+            "LEEF:1.0|Acme|Widget|1.0|my_app::net|sev=9\tsrc=10.0.0.1\tmsg=denied\n"
+        );
+    }
+
+    #[test]
+    fn renders_leef_2_with_custom_delimiter() {
+        let formatter = LeefFormatter::new("Ac|me", "Widget", "1.0")
+            .with_delimiter('^')
+            .with_event_id("4625");
+        let props = vec![(String::from("user name"), Property::Static("a^b\nc".into()))];
+
+        assert_eq!(
+            format(&formatter, &props, "x=y"),
+            "LEEF:2.0|Ac\\|me|Widget|1.0|4625|^|sev=9^username=a\\^b\\nc^msg=x=y\n"
+        );
+    }
+
+    #[test]
+    fn writes_tab_delimiter_as_hex() {
+        let formatter = LeefFormatter::default().with_event_id("1");
+
+        assert!(format(&formatter, &[], "hi").contains("|1|x09|sev=9\tmsg=hi"));
+    }
+
+    #[test]
+    fn delimits_attributes_after_values_ending_in_a_pipe() {
+        let formatter = LeefFormatter::new("Acme", "Widget", "1.0").with_event_id("1");
+        let props = vec![(String::from("cmd"), Property::Static("ls |".into()))];
+
+        assert_eq!(
+            format(&formatter, &props, "ran"),
+            "LEEF:2.0|Acme|Widget|1.0|1|x09|sev=9\tcmd=ls |\tmsg=ran\n"
+        );
+    }
+}
@@ -1,27 +1,84 @@
 //! # Formatters
 //! Formatters define how the output is displayed or structured.
 
+mod cef_formatter;
+mod color;
+#[cfg(feature = "json")]
+mod gelf_formatter;
 #[cfg(feature = "json")]
 mod json_formatter;
+mod leef_formatter;
+mod pattern_formatter;
 mod simple_formatter;
 
+pub use cef_formatter::*;
+pub use color::ColorChoice;
+#[cfg(feature = "json")]
+pub use gelf_formatter::*;
 #[cfg(feature = "json")]
 pub use json_formatter::*;
+pub use leef_formatter::*;
+pub use pattern_formatter::*;
 pub use simple_formatter::*;
 
-use crate::{Property, TacitOutput};
-use log::Record;
+use crate::Property;
+use log::{Level, Record};
+use std::io;
 
+/// Renders log records. Formatting is kept separate from writing so that an entry can be
+/// rendered once, outside of any output lock, and then handed to an output as is.
 pub trait TacitFormatter: Default + Send + Sync {
-    fn log<O>(
+    /// Render a single entry, including its trailing newline, by appending it to `buf`.
+    fn format(
         &self,
-        output: &mut O,
+        buf: &mut Vec<u8>,
         record: &Record,
         msg_prop: &str,
         default_props: &[(String, Property)],
         ignore_empty_props: bool,
-    ) where
-        O: TacitOutput;
+    ) -> io::Result<()>;
+
+    /// Called once the formatter belongs to a logger, with whether the output writes
+    /// entries at a level to a terminal. Formatters that color entries use it to resolve
+    /// `ColorChoice::Auto`.
+    fn set_terminal(&mut self, is_terminal: &dyn Fn(Level) -> bool) {
+        let _ = is_terminal;
+    }
+}
+
+/// Check a strftime format up front, chrono fails to render invalid ones, e.g. `%Q` or a
+/// trailing `%`, at the time they are used.
+pub(crate) fn check_time_format(format: &str) -> io::Result<()> {
+    use chrono::format::{Item, StrftimeItems};
+
+    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid time format `{}`", format),
+        ));
+    }
+    Ok(())
+}
+
+/// Collect the key value pairs attached to a record, rendered as strings.
+#[cfg(feature = "kv")]
+pub(crate) fn kv_strings(record: &Record) -> Vec<(String, String)> {
+    struct Collector(Vec<(String, String)>);
+
+    impl<'kvs> log::kv::Visitor<'kvs> for Collector {
+        fn visit_pair(
+            &mut self,
+            key: log::kv::Key<'kvs>,
+            value: log::kv::Value<'kvs>,
+        ) -> Result<(), log::kv::Error> {
+            self.0.push((key.to_string(), value.to_string()));
+            Ok(())
+        }
+    }
+
+    let mut collector = Collector(Vec::new());
+    let _ = record.key_values().visit(&mut collector);
+    collector.0
 }
 
 #[cfg(feature = "kv")]
@@ -0,0 +1,609 @@
+//! # Pattern Formatter
+//! Structures log output as human readable lines, following a template.
+//!
+//! ```sh
+//! 2026-10-17 12:00:01.123 INFO  [my_app::module] logging a thing user=alice
+//! ```
+//!
+//! Templates are compiled once and made of literal text and placeholders in
+//! braces, `{{` and `}}` write a literal brace.
+//!
+//! | Placeholder | Value |
+//! |---|---|
+//! | `{timestamp}`, `{timestamp(%H:%M:%S)}` | current time, optionally with a strftime format |
+//! | `{level}` | record level |
+//! | `{target}` | record target |
+//! | `{module}` | module path |
+//! | `{file}`, `{line}`, `{location}` | source file, line, or both as `file:line` |
+//! | `{thread}` | current thread's name, or its id when unnamed |
+//! | `{message}` | the log message |
+//! | `{prop(name)}` | the logger's property `name` |
+//! | `{kv}` | the record's key value pairs as `key=value` |
+//!
+//! Any placeholder can end with a modifier `:[align][width][.max]`, where align is
+//! `<`, `>` or `^`. Values longer than `max` characters are truncated, and values
+//! shorter than `width` are padded with spaces, e.g. `{level:<5}` or `{target:>12.12}`.
+//!
+//! With colors enabled `{level}` is colored by level, and the keys of `{kv}` can be
+//! dimmed to set them apart from values.
+
+use super::color::{level_style, Colors, RESET};
+use crate::{formatters::TacitFormatter, ColorChoice, Property, StaticProperty};
+use chrono::{Local, Utc};
+use log::{Level, Record};
+use std::io::{self, Write};
+
+const DEFAULT_PATTERN: &str = "{timestamp} {level:<5} [{target}] {message} {kv}";
+const DEFAULT_TIMESTAMP: &str = "%Y-%m-%d %H:%M:%S%.3f";
+
+#[derive(Clone, Copy)]
+enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+#[derive(Clone, Copy)]
+struct Modifier {
+    align: Align,
+    width: usize,
+    max: Option<usize>,
+}
+
+impl Default for Modifier {
+    fn default() -> Self {
+        Self {
+            align: Align::Left,
+            width: 0,
+            max: None,
+        }
+    }
+}
+
+enum Field {
+    Timestamp(String),
+    Level,
+    Target,
+    Module,
+    File,
+    Line,
+    Location,
+    Thread,
+    Message,
+    Property(String),
+    Kv,
+}
+
+enum Piece {
+    Literal(String),
+    Field(Field, Modifier),
+}
+
+pub struct PatternFormatter {
+    pieces: Vec<Piece>,
+    local_time: bool,
+    colors: Colors,
+    key_colors: bool,
+}
+
+impl Default for PatternFormatter {
+    fn default() -> Self {
+        Self::new(DEFAULT_PATTERN).expect("default pattern is valid")
+    }
+}
+
+impl PatternFormatter {
+    /// Compile a template, failing with `InvalidInput` when it is malformed or uses an
+    /// unknown placeholder.
+    pub fn new(pattern: &str) -> io::Result<Self> {
+        Ok(Self {
+            pieces: compile(pattern)?,
+            local_time: false,
+            colors: Colors::default(),
+            key_colors: false,
+        })
+    }
+
+    /// Render timestamps in the local time zone instead of UTC.
+    pub fn set_local_time(&mut self, local_time: bool) {
+        self.local_time = local_time;
+    }
+
+    /// Render timestamps in the local time zone instead of UTC. Useful for chaining operations.
+    #[must_use]
+    pub fn with_local_time(mut self, local_time: bool) -> Self {
+        self.set_local_time(local_time);
+        self
+    }
+
+    /// Style entries with ANSI colors. `ColorChoice::Auto` is resolved against the streams
+    /// the logger's output writes to.
+    pub fn set_color(&mut self, color: ColorChoice) {
+        self.colors = Colors::new(color);
+    }
+
+    /// Style entries with ANSI colors. `ColorChoice::Auto` is resolved against the streams
+    /// the logger's output writes to.
+    /// Useful for chaining operations.
+    #[must_use]
+    pub fn with_color(mut self, color: ColorChoice) -> Self {
+        self.set_color(color);
+        self
+    }
+
+    /// Dim the keys of `{kv}` when colors are enabled.
+    pub fn set_key_colors(&mut self, key_colors: bool) {
+        self.key_colors = key_colors;
+    }
+
+    /// Dim the keys of `{kv}` when colors are enabled. Useful for chaining operations.
+    #[must_use]
+    pub fn with_key_colors(mut self, key_colors: bool) -> Self {
+        self.set_key_colors(key_colors);
+        self
+    }
+
+    fn timestamp(&self, format: &str) -> String {
+        if self.local_time {
+            Local::now().format(format).to_string()
+        } else {
+            Utc::now().format(format).to_string()
+        }
+    }
+
+    fn field_value(
+        &self,
+        field: &Field,
+        record: &Record,
+        default_props: &[(String, Property)],
+    ) -> String {
+        match field {
+            Field::Timestamp(format) => self.timestamp(format),
+            Field::Level => record.level().to_string(),
+            Field::Target => record.target().to_string(),
+            Field::Module => record.module_path().unwrap_or_default().to_string(),
+            Field::File => record.file().unwrap_or_default().to_string(),
+            Field::Line => record.line().map(|l| l.to_string()).unwrap_or_default(),
+            Field::Location => match (record.file(), record.line()) {
+                (Some(file), Some(line)) => format!("{}:{}", file, line),
+                (Some(file), None) => file.to_string(),
+                _ => String::new(),
+            },
+            Field::Thread => {
+                let thread = std::thread::current();
+                match thread.name() {
+                    Some(name) => name.to_string(),
+                    None => format!("{:?}", thread.id()),
+                }
+            }
+            Field::Message => format!("{}", record.args()),
+            Field::Property(name) => default_props
+                .iter()
+                .find(|prop| &prop.0 == name)
+                .map(|prop| prop.1.pattern_value(record))
+                .unwrap_or_default(),
+            Field::Kv => kv_pairs(
+                record,
+                self.colors.enabled(record.level()) && self.key_colors,
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "kv")]
+fn kv_pairs(record: &Record, key_colors: bool) -> String {
+    use super::{
+        color::KEY_STYLE,
+        simple_formatter::{encode_key, encode_value},
+    };
+
+    super::kv_strings(record)
+        .iter()
+        .map(|(key, value)| {
+            if key_colors {
+                format!(
+                    "{}{}{}={}",
+                    KEY_STYLE,
+                    encode_key(key),
+                    RESET,
+                    encode_value(value)
+                )
+            } else {
+                format!("{}={}", encode_key(key), encode_value(value))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(not(feature = "kv"))]
+fn kv_pairs(_record: &Record, _key_colors: bool) -> String {
+    String::new()
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn compile(pattern: &str) -> io::Result<Vec<Piece>> {
+    let mut pieces = Vec::new();
+    let mut literal = String::new();
+    let mut chars = pattern.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '}' => return Err(invalid(String::from("unmatched `}` in pattern"))),
+            '{' => {
+                let mut placeholder = String::new();
+                let mut in_argument = false;
+                loop {
+                    match chars.next() {
+                        Some('(') if !in_argument => {
+                            in_argument = true;
+                            placeholder.push('(');
+                        }
+                        Some(')') if in_argument => {
+                            in_argument = false;
+                            placeholder.push(')');
+                        }
+                        Some('}') if !in_argument => break,
+                        Some(c) => placeholder.push(c),
+                        None => return Err(invalid(String::from("unclosed `{` in pattern"))),
+                    }
+                }
+
+                if !literal.is_empty() {
+                    pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+                }
+                pieces.push(compile_placeholder(&placeholder)?);
+            }
+            c => literal.push(c),
+        }
+    }
+
+    if !literal.is_empty() {
+        pieces.push(Piece::Literal(literal));
+    }
+    Ok(pieces)
+}
+
+fn compile_placeholder(placeholder: &str) -> io::Result<Piece> {
+    // the argument may itself contain `:`, e.g. `{timestamp(%H:%M)}`
+    let (head, modifier) = match placeholder.rfind(')') {
+        Some(end) => match placeholder[end..].find(':') {
+            Some(colon) => placeholder.split_at(end + colon),
+            None => (placeholder, ""),
+        },
+        None => match placeholder.find(':') {
+            Some(colon) => placeholder.split_at(colon),
+            None => (placeholder, ""),
+        },
+    };
+
+    let (name, argument) = match head.find('(') {
+        Some(start) if head.ends_with(')') => {
+            (&head[..start], Some(&head[start + 1..head.len() - 1]))
+        }
+        Some(_) => {
+            return Err(invalid(format!(
+                "malformed placeholder `{{{}}}`",
+                placeholder
+            )))
+        }
+        None => (head, None),
+    };
+
+    let field = match (name.trim(), argument) {
+        ("timestamp", None) => Field::Timestamp(DEFAULT_TIMESTAMP.to_string()),
+        ("timestamp", Some(format)) => {
+            super::check_time_format(format)?;
+            Field::Timestamp(format.to_string())
+        }
+        ("level", None) => Field::Level,
+        ("target", None) => Field::Target,
+        ("module", None) => Field::Module,
+        ("file", None) => Field::File,
+        ("line", None) => Field::Line,
+        ("location", None) => Field::Location,
+        ("thread", None) => Field::Thread,
+        ("message", None) => Field::Message,
+        ("prop", Some(name)) if !name.is_empty() => Field::Property(name.to_string()),
+        ("kv", None) => Field::Kv,
+        _ => {
+            return Err(invalid(format!(
+                "unknown placeholder `{{{}}}`",
+                placeholder
+            )))
+        }
+    };
+
+    let modifier = match modifier.strip_prefix(':') {
+        Some(modifier) => compile_modifier(modifier)
+            .ok_or_else(|| invalid(format!("malformed modifier in `{{{}}}`", placeholder)))?,
+        None => Modifier::default(),
+    };
+
+    Ok(Piece::Field(field, modifier))
+}
+
+fn compile_modifier(modifier: &str) -> Option<Modifier> {
+    let (align, rest) = match modifier.chars().next() {
+        Some('<') => (Align::Left, &modifier[1..]),
+        Some('>') => (Align::Right, &modifier[1..]),
+        Some('^') => (Align::Center, &modifier[1..]),
+        _ => (Align::Left, modifier),
+    };
+
+    let (width, max) = match rest.split_once('.') {
+        Some((width, max)) => (width, Some(max.parse().ok()?)),
+        None => (rest, None),
+    };
+    let width = if width.is_empty() {
+        0
+    } else {
+        width.parse().ok()?
+    };
+
+    Some(Modifier { align, width, max })
+}
+
+/// Truncate and pad a value according to its modifier, styling the value but not its padding.
+fn write_modified(
+    buf: &mut Vec<u8>,
+    value: &str,
+    modifier: Modifier,
+    style: Option<&str>,
+) -> io::Result<()> {
+    let value = match modifier.max {
+        Some(max) => match value.char_indices().nth(max) {
+            Some((end, _)) => &value[..end],
+            None => value,
+        },
+        None => value,
+    };
+
+    let padding = modifier.width.saturating_sub(value.chars().count());
+    let (before, after) = match modifier.align {
+        Align::Left => (0, padding),
+        Align::Right => (padding, 0),
+        Align::Center => (padding / 2, padding - padding / 2),
+    };
+    match style {
+        Some(style) => write!(
+            buf,
+            "{:before$}{}{}{}{:after$}",
+            "",
+            style,
+            value,
+            RESET,
+            "",
+            before = before,
+            after = after
+        ),
+        None => write!(
+            buf,
+            "{:before$}{}{:after$}",
+            "",
+            value,
+            "",
+            before = before,
+            after = after
+        ),
+    }
+}
+
+impl TacitFormatter for PatternFormatter {
+    fn format(
+        &self,
+        buf: &mut Vec<u8>,
+        record: &Record,
+        _msg_prop: &str,
+        default_props: &[(String, Property)],
+        _ignore_empty_props: bool,
+    ) -> io::Result<()> {
+        // placeholders that rendered nothing, such as `{kv}`, should not leave trailing
+        // spaces, so remember where the spaces separating them from the rest begin
+        let mut spaces = None;
+        let mut trailing_empty = None;
+
+        for piece in &self.pieces {
+            match piece {
+                Piece::Literal(text) => {
+                    let trimmed = text.trim_end_matches(' ').len();
+                    if trimmed > 0 {
+                        spaces = Some(buf.len() + trimmed);
+                        trailing_empty = None;
+                    } else {
+                        spaces = spaces.or(Some(buf.len()));
+                    }
+                    buf.extend_from_slice(text.as_bytes());
+                }
+                Piece::Field(field, modifier) => {
+                    let value = self.field_value(field, record, default_props);
+                    if value.is_empty() {
+                        trailing_empty = trailing_empty.or(spaces).or(Some(buf.len()));
+                    } else {
+                        trailing_empty = None;
+                    }
+                    spaces = None;
+
+                    let style = match field {
+                        Field::Level if self.colors.enabled(record.level()) => {
+                            Some(level_style(record.level()))
+                        }
+                        _ => None,
+                    };
+                    write_modified(buf, &value, *modifier, style)?;
+                }
+            }
+        }
+
+        if let (Some(start), Some(Piece::Field(..))) = (trailing_empty, self.pieces.last()) {
+            buf.truncate(start);
+        }
+
+        writeln!(buf)
+    }
+
+    fn set_terminal(&mut self, is_terminal: &dyn Fn(Level) -> bool) {
+        self.colors.set_terminal(is_terminal);
+    }
+}
+
+impl StaticProperty {
+    pub fn pattern_value(&self) -> String {
+        match self {
+            Self::String(v) => v.to_string(),
+            Self::Number(v) => v.to_string(),
+            Self::Null => String::new(),
+        }
+    }
+}
+
+impl Property {
+    pub fn pattern_value(&self, record: &Record) -> String {
+        match self {
+            Self::Static(prop) => prop.pattern_value(),
+            Self::Function(f) => f(record).pattern_value(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    fn format(pattern: &str, props: &[(String, Property)]) -> String {
+        let mut buf = Vec::new();
+        PatternFormatter::new(pattern)
+            .unwrap()
+            .format(
+                &mut buf,
+                &Record::builder()
+                    .args(format_args!("connection lost"))
+                    .level(Level::Warn)
+                    .target("my_app::net")
+                    .module_path(Some("my_app::net::tcp"))
+                    .file(Some("src/net/tcp.rs"))
+                    .line(Some(42))
+                    .build(),
+                "msg",
+                props,
+                true,
+            )
+            .unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn renders_placeholders() {
+        let props = vec![(String::from("host"), Property::Static("web-1".into()))];
+
+        assert_eq!(
+            format(
+                "{level} {{{target}}} {module} {location} {file}#{line} {prop(host)} {prop(missing)}: {message} {kv}",
+                &props
+            ),
+            "WARN {my_app::net} my_app::net::tcp src/net/tcp.rs:42 src/net/tcp.rs#42 web-1 : connection lost\n"
+        );
+
+        let thread = std::thread::Builder::new()
+            .name(String::from("worker"))
+            .spawn(|| format("[{thread}]", &[]))
+            .unwrap();
+        assert_eq!(thread.join().unwrap(), "[worker]\n");
+    }
+
+    #[test]
+    fn trims_only_spaces_left_by_empty_placeholders() {
+        assert_eq!(format("{message}  {kv} {kv:<4}", &[]), "connection lost\n");
+        assert_eq!(format("{message}] {kv}", &[]), "connection lost]\n");
+        assert_eq!(format("{message} {kv} ", &[]), "connection lost  \n");
+        assert_eq!(format("{message}:  ", &[]), "connection lost:  \n");
+        assert_eq!(format("{kv}", &[]), "\n");
+
+        let mut buf = Vec::new();
+        PatternFormatter::new("{message} {kv}")
+            .unwrap()
+            .format(
+                &mut buf,
+                &Record::builder().args(format_args!("padded  ")).build(),
+                "msg",
+                &[],
+                true,
+            )
+            .unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), "padded  \n");
+    }
+
+    #[test]
+    fn applies_modifiers() {
+        assert_eq!(
+            format(
+                "|{level:<6}|{level:>6}|{level:^7}|{target:.6}|{target:>8.3}|",
+                &[]
+            ),
+            "|WARN  |  WARN| WARN  |my_app|     my_|\n"
+        );
+    }
+
+    #[test]
+    fn colors_level() {
+        let mut buf = Vec::new();
+        PatternFormatter::new("{level:<6}|{message}")
+            .unwrap()
+            .with_color(ColorChoice::Always)
+            .format(
+                &mut buf,
+                &Record::builder()
+                    .args(format_args!("hi"))
+                    .level(Level::Error)
+                    .build(),
+                "msg",
+                &[],
+                true,
+            )
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "\x1b[31mERROR\x1b[0m |hi\n"
+        );
+    }
+
+    #[test]
+    fn formats_timestamps() {
+        let line = format("{timestamp(%H:%M)} {timestamp}", &[]);
+        let (time, timestamp) = line.trim_end().split_once(' ').unwrap();
+
+        assert!(chrono::NaiveTime::parse_from_str(time, "%H:%M").is_ok());
+        assert!(chrono::NaiveDateTime::parse_from_str(timestamp, DEFAULT_TIMESTAMP).is_ok());
+    }
+
+    #[test]
+    fn rejects_malformed_patterns() {
+        for pattern in [
+            "{level",
+            "level}",
+            "{unknown}",
+            "{level:x}",
+            "{prop()}",
+            "{timestamp(%Q)}",
+            "{timestamp(%H %)}",
+        ]
+        .iter()
+        {
+            let err = PatternFormatter::new(pattern).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{}", pattern);
+        }
+    }
+}
@@ -1,49 +1,262 @@
 //! # Simple Formatter
-//! Structures log output in a simple parsable format.
+//! Structures log output as [logfmt](https://brandur.org/logfmt).
 //!
 //! ```sh
-//! timeStamp="2021-03-29T15:16:25.683809406+00:00" level="INFO" msg="logging a thing"
+//! timeStamp=2021-03-29T15:16:25.683809406+00:00 level=INFO msg="logging a thing"
 //! ```
+//!
+//! Values are only quoted when they are empty or contain spaces, `=`, quotes,
+//! backslashes or control characters, which are escaped. Characters that are not
+//! allowed in keys are replaced with `_`. `parse_logfmt` reads lines back into
+//! their keys and values.
+//!
+//! With colors enabled the `level` property is colored by level, and keys can be
+//! dimmed to set them apart from values.
 
-use crate::{formatters::TacitFormatter, Property, StaticProperty, TacitOutput};
-use log::Record;
+use super::color::{level_style, Colors, KEY_STYLE, RESET};
+use crate::{formatters::TacitFormatter, ColorChoice, Property, StaticProperty};
+use log::{Level, Record};
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+};
 
 #[derive(Default)]
-pub struct SimpleFormatter {}
+pub struct SimpleFormatter {
+    colors: Colors,
+    key_colors: bool,
+}
+
+impl SimpleFormatter {
+    /// Style entries with ANSI colors. `ColorChoice::Auto` is resolved against the streams
+    /// the logger's output writes to.
+    pub fn set_color(&mut self, color: ColorChoice) {
+        self.colors = Colors::new(color);
+    }
+
+    /// Style entries with ANSI colors. `ColorChoice::Auto` is resolved against the streams
+    /// the logger's output writes to.
+    /// Useful for chaining operations.
+    #[must_use]
+    pub fn with_color(mut self, color: ColorChoice) -> Self {
+        self.set_color(color);
+        self
+    }
+
+    /// Dim keys when colors are enabled.
+    pub fn set_key_colors(&mut self, key_colors: bool) {
+        self.key_colors = key_colors;
+    }
+
+    /// Dim keys when colors are enabled. Useful for chaining operations.
+    #[must_use]
+    pub fn with_key_colors(mut self, key_colors: bool) -> Self {
+        self.set_key_colors(key_colors);
+        self
+    }
+
+    /// Append `key=value` to a line, skipping keys with nothing left after sanitizing.
+    fn push_pair(
+        &self,
+        item: &mut String,
+        key: &str,
+        value: &str,
+        style: Option<&str>,
+        colors: bool,
+    ) {
+        let key = encode_key(key);
+        if key.is_empty() {
+            return;
+        }
+
+        if !item.is_empty() {
+            item.push(' ');
+        }
+        if colors && self.key_colors {
+            let _ = write!(item, "{}{}{}=", KEY_STYLE, key, RESET);
+        } else {
+            let _ = write!(item, "{}=", key);
+        }
+        match style {
+            Some(style) if colors => {
+                let _ = write!(item, "{}{}{}", style, value, RESET);
+            }
+            _ => item.push_str(value),
+        }
+    }
+}
 
 impl TacitFormatter for SimpleFormatter {
-    fn log<O>(
+    fn format(
         &self,
-        output: &mut O,
+        buf: &mut Vec<u8>,
         record: &Record,
         msg_prop: &str,
         default_props: &[(String, Property)],
         ignore_empty_props: bool,
-    ) where
-        O: TacitOutput,
-    {
-        let msg = format!("{}", record.args());
-
+    ) -> io::Result<()> {
         let mut item = String::new();
+        let colors = self.colors.enabled(record.level());
 
         for prop in default_props {
             let value = prop.1.simple_value(record);
             if ignore_empty_props && value.is_empty() {
                 continue;
             }
-            item = format!("{} {}={}", item, prop.0, value);
+            let style = if prop.0 == "level" {
+                Some(level_style(record.level()))
+            } else {
+                None
+            };
+            self.push_pair(&mut item, &prop.0, &value, style, colors);
+        }
+
+        self.push_pair(
+            &mut item,
+            msg_prop,
+            &encode_value(&format!("{}", record.args())),
+            None,
+            colors,
+        );
+
+        #[cfg(feature = "kv")]
+        for (key, value) in super::kv_strings(record) {
+            self.push_pair(&mut item, &key, &encode_value(&value), None, colors);
+        }
+
+        writeln!(buf, "{}", item)
+    }
+
+    fn set_terminal(&mut self, is_terminal: &dyn Fn(Level) -> bool) {
+        self.colors.set_terminal(is_terminal);
+    }
+}
+
+fn is_key_char(c: char) -> bool {
+    !c.is_whitespace() && !c.is_control() && c != '=' && c != '"'
+}
+
+/// Replace characters that can not appear in a logfmt key with `_`.
+pub(super) fn encode_key(key: &str) -> String {
+    key.chars()
+        .map(|c| if is_key_char(c) { c } else { '_' })
+        .collect()
+}
+
+/// Render a logfmt value, quoting and escaping it only when needed.
+pub(super) fn encode_value(value: &str) -> String {
+    let needs_quotes = value.is_empty()
+        || value
+            .chars()
+            .any(|c| c.is_whitespace() || c == '=' || c == '"' || c == '\\' || c.is_control());
+    if !needs_quotes {
+        return value.to_string();
+    }
+
+    let mut encoded = String::with_capacity(value.len() + 2);
+    encoded.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => encoded.push_str("\\\""),
+            '\\' => encoded.push_str("\\\\"),
+            '\n' => encoded.push_str("\\n"),
+            '\r' => encoded.push_str("\\r"),
+            '\t' => encoded.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(encoded, "\\u{:04x}", c as u32);
+            }
+            c => encoded.push(c),
+        }
+    }
+    encoded.push('"');
+    encoded
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Parse a logfmt line into its keys and unescaped values. Keys without a value, such as
+/// `debug` in `debug msg=hi`, have an empty value.
+pub fn parse_logfmt(line: &str) -> io::Result<Vec<(String, String)>> {
+    let mut pairs = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            return Ok(pairs);
+        }
+
+        let mut key = String::new();
+        while let Some(&c) = chars.peek() {
+            if !is_key_char(c) {
+                break;
+            }
+            key.push(c);
+            chars.next();
+        }
+        if key.is_empty() {
+            return Err(invalid("expected a key"));
         }
 
-        item = format!("{} {}=\"{}\"", item, msg_prop, msg);
+        let mut value = String::new();
+        if chars.peek() == Some(&'=') {
+            chars.next();
+            if chars.peek() == Some(&'"') {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('"') => value.push('"'),
+                            Some('\\') => value.push('\\'),
+                            Some('n') => value.push('\n'),
+                            Some('r') => value.push('\r'),
+                            Some('t') => value.push('\t'),
+                            Some('u') => {
+                                let code: String = chars.by_ref().take(4).collect();
+                                let c = u32::from_str_radix(&code, 16)
+                                    .ok()
+                                    .and_then(char::from_u32)
+                                    .ok_or_else(|| invalid("invalid unicode escape"))?;
+                                value.push(c);
+                            }
+                            _ => return Err(invalid("invalid escape")),
+                        },
+                        Some(c) => value.push(c),
+                        None => return Err(invalid("unterminated quoted value")),
+                    }
+                }
+                if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+                    return Err(invalid("expected whitespace after quoted value"));
+                }
+            } else {
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() {
+                        break;
+                    }
+                    if c == '"' || c == '=' {
+                        return Err(invalid("unexpected character in unquoted value"));
+                    }
+                    value.push(c);
+                    chars.next();
+                }
+            }
+        } else if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+            return Err(invalid("unexpected character in key"));
+        }
 
-        writeln!(output, "{}", item.trim()).expect("Unable to write to logger output");
+        pairs.push((key, value));
     }
 }
 
 impl StaticProperty {
     pub fn simple_value(&self) -> String {
         match self {
-            Self::String(v) => format!("\"{}\"", v),
+            Self::String(v) => encode_value(v),
             Self::Number(v) => v.to_string(),
             Self::Null => String::new(),
         }
@@ -58,3 +271,137 @@ impl Property {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    fn format(props: &[(String, Property)], message: &str) -> String {
+        let mut buf = Vec::new();
+        SimpleFormatter::default()
+            .format(
+                &mut buf,
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(Level::Info)
+                    .build(),
+                "msg",
+                props,
+                false,
+            )
+            .unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn quotes_only_when_needed() {
+        let props = vec![
+            (String::from("level"), Property::Static("INFO".into())),
+            (String::from("pid"), Property::Static(42i64.into())),
+            (String::from("host name"), Property::Static("".into())),
+            (
+                String::from("empty"),
+                Property::Static(StaticProperty::Null),
+            ),
+        ];
+
+        assert_eq!(
+            format(&props, "started"),
+            "level=INFO pid=42 host_name=\"\" empty= msg=started\n"
+        );
+    }
+
+    #[test]
+    fn colors_level_and_keys() {
+        let props = vec![(String::from("level"), Property::Static("INFO".into()))];
+        let mut buf = Vec::new();
+        SimpleFormatter::default()
+            .with_color(ColorChoice::Always)
+            .with_key_colors(true)
+            .format(
+                &mut buf,
+                &Record::builder()
+                    .args(format_args!("hi"))
+                    .level(Level::Info)
+                    .build(),
+                "msg",
+                &props,
+                false,
+            )
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "\x1b[2mlevel\x1b[0m=\x1b[32mINFO\x1b[0m \x1b[2mmsg\x1b[0m=hi\n"
+        );
+    }
+
+    #[test]
+    fn round_trips_through_parser() {
+        let messages = [
+            "plain",
+            "with spaces",
+            "a=b",
+            "say \"hi\"",
+            "back\\slash",
+            "multi\nline\r\n\ttabbed",
+            "bell \u{7} and unicode é",
+            "no\u{a0}break",
+            "line\u{2028}separator",
+            "",
+        ];
+        let props = vec![(String::from("k=\"e\u{a0}y"), Property::Static("x y".into()))];
+
+        for message in messages.iter() {
+            let line = format(&props, message);
+            assert_eq!(line.matches('\n').count(), 1, "{:?}", line);
+            assert_eq!(
+                parse_logfmt(&line).unwrap(),
+                vec![
+                    (String::from("k__e_y"), String::from("x y")),
+                    (String::from("msg"), message.to_string()),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert!(parse_logfmt("msg=\"unterminated").is_err());
+        assert!(parse_logfmt("msg=\"bad\\q\"").is_err());
+        assert!(parse_logfmt("msg=a\"b").is_err());
+        assert_eq!(
+            parse_logfmt("debug msg=hi").unwrap(),
+            vec![
+                (String::from("debug"), String::new()),
+                (String::from("msg"), String::from("hi")),
+            ]
+        );
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn renders_kv_pairs() {
+        let kvs = [("user id", "a b"), ("count", "3")];
+        let mut buf = Vec::new();
+        SimpleFormatter::default()
+            .format(
+                &mut buf,
+                &Record::builder()
+                    .args(format_args!("login"))
+                    .level(Level::Info)
+                    .key_values(&kvs)
+                    .build(),
+                "msg",
+                &[],
+                false,
+            )
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "msg=login user_id=\"a b\" count=3\n"
+        );
+    }
+}
@@ -38,10 +38,26 @@
 //! use tacit::{JsonFormatter, Logger, SimpleConsoleOutput};
 //!
 //! let json_logger = Logger::<SimpleConsoleOutput, JsonFormatter>::default();
-//! tacit::new().with_logger(json_logger).log().unwrap();
+//! let _guard = tacit::new().with_logger(json_logger).log().unwrap();
 //! log::info!("logging some info!");
 //! ```
 //!
+//! Loggers do not need to share an output or formatter, any combination can be
+//! registered with the same `TacitLogger`:
+//!
+//! ```rust
+//! use tacit::{JsonFormatter, Logger, SimpleConsoleOutput, SimpleFormatter};
+//!
+//! let json_logger = Logger::<SimpleConsoleOutput, JsonFormatter>::default();
+//! let simple_logger = Logger::<SimpleConsoleOutput, SimpleFormatter>::default();
+//! let _guard = tacit::new()
+//!     .with_logger(json_logger)
+//!     .with_logger(simple_logger)
+//!     .log()
+//!     .unwrap();
+//! log::info!("logging some info twice!");
+//! ```
+//!
 //! In the event that a [formatter](#formatters) or [output](#outputs) has specific
 //! configuration options, they can be used like this:
 //!
@@ -51,19 +67,33 @@
 //! let output = SimpleConsoleOutput::default(); // with options...
 //! let formatter = JsonFormatter::default(); // with options...
 //! let json_logger = Logger::new(output, formatter);
-//! tacit::new().with_logger(json_logger).log().unwrap();
+//! let _guard = tacit::new().with_logger(json_logger).log().unwrap();
 //! log::info!("logging some info!");
 //! ```
 //!
 
+mod error_policy;
 mod formatters;
 mod logger;
 mod outputs;
 mod properties;
+#[cfg(test)]
+mod test_util;
+#[cfg(feature = "threaded")]
+mod writer;
 
-pub use crate::{formatters::*, logger::*, outputs::*, properties::*};
+pub use crate::{
+    error_policy::{ErrorPolicy, ErrorReporter},
+    formatters::*,
+    logger::*,
+    outputs::*,
+    properties::*,
+};
 pub use log::LevelFilter;
 use log::{Log, Metadata, Record};
+use std::sync::Arc;
+#[cfg(feature = "threaded")]
+pub use writer::{Backpressure, DropCounter};
 
 #[cfg(feature = "kv")]
 pub use kv_log_macro::{debug, error, info, trace, warn};
@@ -71,13 +101,14 @@ pub use kv_log_macro::{debug, error, info, trace, warn};
 #[cfg(not(feature = "kv"))]
 pub use log::{debug, error, info, trace, warn};
 
-/// Main logger abstraction for Tacit. Combines one or more `Logger` implementations.
-pub struct TacitLogger<O: TacitOutput, F: TacitFormatter> {
-    loggers: Vec<Box<Logger<O, F>>>,
+/// Main logger abstraction for Tacit. Combines one or more `Logger` implementations,
+/// each of which may use a different output and formatter.
+pub struct TacitLogger {
+    loggers: Vec<Arc<dyn TacitLog>>,
     max_level: log::LevelFilter,
 }
 
-impl<O: TacitOutput, F: TacitFormatter> Default for TacitLogger<O, F> {
+impl Default for TacitLogger {
     fn default() -> Self {
         Self {
             loggers: Vec::new(),
@@ -86,28 +117,68 @@ impl<O: TacitOutput, F: TacitFormatter> Default for TacitLogger<O, F> {
     }
 }
 
-pub fn new<O: TacitOutput, F: TacitFormatter>() -> TacitLogger<O, F> {
+pub fn new() -> TacitLogger {
     TacitLogger::default()
 }
 
-impl<O: 'static + TacitOutput, F: 'static + TacitFormatter> TacitLogger<O, F> {
+impl TacitLogger {
     /// Add a logger to the pile
     #[must_use]
-    pub fn with_logger(mut self, logger: Logger<O, F>) -> Self {
+    pub fn with_logger<O: 'static + TacitOutput, F: 'static + TacitFormatter>(
+        self,
+        logger: Logger<O, F>,
+    ) -> Self {
+        self.with_boxed_logger(Box::new(logger.finalize()))
+    }
+
+    /// Add an already boxed logger to the pile, useful for custom `TacitLog` implementations
+    #[must_use]
+    pub fn with_boxed_logger(mut self, logger: Box<dyn TacitLog>) -> Self {
         self.max_level = std::cmp::max(self.max_level, logger.level_filter());
-        self.loggers.push(Box::new(logger.finalize()));
+        self.loggers.push(Arc::from(logger));
         self
     }
 
-    /// Starts logging system so that  `log` macros work
-    pub fn log(self) -> Result<(), log::SetLoggerError> {
+    /// Starts logging system so that  `log` macros work. The returned `TacitGuard` should
+    /// be kept alive until the application exits, so that no entries are lost.
+    pub fn log(self) -> Result<TacitGuard, log::SetLoggerError> {
+        let guard = self.guard();
         log::set_max_level(self.max_level);
-        log::set_boxed_logger(Box::new(self))
+        log::set_boxed_logger(Box::new(self)).map(|_| guard)
+    }
+
+    fn guard(&self) -> TacitGuard {
+        TacitGuard {
+            loggers: self.loggers.clone(),
+        }
+    }
+}
+
+/// Flushes every logger and stops their background writers when dropped. Returned from
+/// `TacitLogger::log`, hold on to it for as long as logging is needed:
+///
+/// ```rust
+/// let _guard = tacit::new().log().unwrap();
+/// ```
+#[must_use = "dropping the guard stops background writers immediately"]
+pub struct TacitGuard {
+    loggers: Vec<Arc<dyn TacitLog>>,
+}
+
+impl Drop for TacitGuard {
+    fn drop(&mut self) {
+        for logger in &self.loggers {
+            logger.shutdown();
+        }
     }
 }
 
-impl<O: TacitOutput, F: TacitFormatter> Log for TacitLogger<O, F> {
-    fn flush(&self) {}
+impl Log for TacitLogger {
+    fn flush(&self) {
+        for logger in &self.loggers {
+            logger.flush();
+        }
+    }
 
     fn enabled(&self, metadata: &Metadata) -> bool {
         metadata.level().to_level_filter() <= self.max_level
@@ -124,5 +195,74 @@ impl<O: TacitOutput, F: TacitFormatter> Log for TacitLogger<O, F> {
     }
 }
 
-#[cfg(test)]
-mod tests {}
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+    use crate::test_util::SharedOutput;
+
+    #[test]
+    fn mixes_formatters_and_outputs() {
+        let json_output = SharedOutput::default();
+        let simple_output = SharedOutput::default();
+
+        let tacit = new()
+            .with_logger(Logger::new(json_output.clone(), JsonFormatter::default()))
+            .with_logger(Logger::new(
+                simple_output.clone(),
+                SimpleFormatter::default(),
+            ));
+
+        Log::log(
+            &tacit,
+            &Record::builder()
+                .args(format_args!("mixed"))
+                .level(log::Level::Info)
+                .target("tests")
+                .build(),
+        );
+
+        assert_eq!(json_output.contents(), "{\"msg\":\"mixed\"}\n");
+        assert_eq!(simple_output.contents(), "msg=mixed\n");
+    }
+
+    #[test]
+    fn flushes_every_logger() {
+        let first = SharedOutput::default();
+        let second = SharedOutput::default();
+
+        let tacit = new()
+            .with_logger(Logger::new(first.clone(), JsonFormatter::default()))
+            .with_logger(Logger::new(second.clone(), SimpleFormatter::default()));
+        tacit.flush();
+
+        assert_eq!(first.flushes(), 1);
+        assert_eq!(second.flushes(), 1);
+    }
+
+    #[cfg(feature = "threaded")]
+    #[test]
+    fn guard_drains_async_loggers() {
+        let output = SharedOutput::default();
+
+        let tacit = new().with_logger(
+            Logger::new(output.clone(), SimpleFormatter::default()).with_async_writes(16),
+        );
+        let guard = tacit.guard();
+
+        for n in 0..3 {
+            Log::log(
+                &tacit,
+                &Record::builder()
+                    .args(format_args!("entry {}", n))
+                    .level(log::Level::Info)
+                    .build(),
+            );
+        }
+        drop(guard);
+
+        assert_eq!(
+            output.contents(),
+            "msg=\"entry 0\"\nmsg=\"entry 1\"\nmsg=\"entry 2\"\n"
+        );
+    }
+}
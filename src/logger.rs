@@ -2,10 +2,38 @@
 //! Combines a formatter and output to produce a log. Tacit can target multiple
 //! loggers simultaneously.
 
-use crate::{Property, StaticProperty, TacitFormatter, TacitOutput};
+#[cfg(feature = "threaded")]
+use crate::writer::{AsyncWriter, Backpressure, DropCounter};
+use crate::{
+    error_policy::{ErrorHandler, ErrorPolicy, ErrorReporter},
+    Property, RecordInfo, StaticProperty, TacitFormatter, TacitOutput,
+};
 use log::{LevelFilter, Log, Metadata, Record};
 use parking_lot::Mutex;
-use std::{collections::HashMap, sync::Arc};
+#[cfg(feature = "threaded")]
+use std::time::Duration;
+use std::{cell::RefCell, collections::HashMap, sync::Arc};
+
+/// Buffers larger than this are released after use rather than kept for reuse.
+const MAX_REUSED_BUFFER: usize = 64 * 1024;
+
+thread_local! {
+    /// Reusable buffer entries are formatted into before being written to an output.
+    static BUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Object safe view of a logger, allowing loggers with different outputs and formatters
+/// to be combined by a single `TacitLogger`.
+pub trait TacitLog: Log {
+    /// Return the `LevelFilter` for the logger
+    fn level_filter(&self) -> LevelFilter;
+
+    /// Flush all pending entries and stop any background work, entries logged afterwards
+    /// are written directly.
+    fn shutdown(&self) {
+        self.flush();
+    }
+}
 
 pub struct Logger<O: TacitOutput, F: TacitFormatter> {
     output: Arc<Mutex<O>>,
@@ -17,8 +45,23 @@ pub struct Logger<O: TacitOutput, F: TacitFormatter> {
     sorted_module_levels: Vec<(String, LevelFilter)>,
     explicit: bool,
     ignore_empty_props: bool,
+    errors: Arc<ErrorHandler>,
+    #[cfg(feature = "threaded")]
+    async_capacity: Option<usize>,
+    #[cfg(feature = "threaded")]
+    backpressure: Backpressure,
+    #[cfg(feature = "threaded")]
+    dropped: DropCounter,
+    #[cfg(feature = "threaded")]
+    drop_report_interval: Duration,
+    #[cfg(feature = "threaded")]
+    writer: Option<AsyncWriter>,
 }
 
+/// Default interval at which dropped entries are reported.
+#[cfg(feature = "threaded")]
+const DEFAULT_DROP_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
 impl<O: TacitOutput, F: TacitFormatter> Default for Logger<O, F> {
     fn default() -> Self {
         let mut logger = Self::new(O::default(), F::default());
@@ -45,23 +88,52 @@ impl<O: TacitOutput, F: TacitFormatter> Logger<O, F> {
             sorted_module_levels: Vec::new(),
             explicit: false,
             ignore_empty_props: false,
+            errors: Arc::new(ErrorHandler::default()),
+            #[cfg(feature = "threaded")]
+            async_capacity: None,
+            #[cfg(feature = "threaded")]
+            backpressure: Backpressure::default(),
+            #[cfg(feature = "threaded")]
+            dropped: DropCounter::default(),
+            #[cfg(feature = "threaded")]
+            drop_report_interval: DEFAULT_DROP_REPORT_INTERVAL,
+            #[cfg(feature = "threaded")]
+            writer: None,
         }
     }
 }
 
 impl<O: TacitOutput, F: TacitFormatter> Log for Logger<O, F> {
     fn log(&self, record: &Record) {
-        let mut output = self.output.lock();
-        self.formatter.log(
-            &mut *output,
-            record,
-            &self.msg_prop,
-            &self.default_props,
-            self.ignore_empty_props,
-        );
+        if O::ignores(record.metadata()) {
+            return;
+        }
+
+        #[cfg(feature = "threaded")]
+        if let Some(writer) = &self.writer {
+            self.report_dropped(writer, false);
+            self.send(writer, record);
+            return;
+        }
+
+        BUFFER.with(|buffer| match buffer.try_borrow_mut() {
+            Ok(mut buffer) => {
+                buffer.clear();
+                self.write(&mut buffer, record);
+                if buffer.capacity() > MAX_REUSED_BUFFER {
+                    *buffer = Vec::new();
+                }
+            }
+            // a property function is logging from within a log call
+            Err(_) => self.write(&mut Vec::new(), record),
+        });
     }
 
     fn enabled(&self, metadata: &Metadata) -> bool {
+        if O::ignores(metadata) {
+            return false;
+        }
+
         let module = metadata.target();
 
         let level = self
@@ -79,7 +151,143 @@ impl<O: TacitOutput, F: TacitFormatter> Log for Logger<O, F> {
         level != &LevelFilter::Off && level >= &self.max_level
     }
 
-    fn flush(&self) {}
+    fn flush(&self) {
+        #[cfg(feature = "threaded")]
+        if let Some(writer) = &self.writer {
+            self.report_dropped(writer, true);
+            writer.flush();
+        }
+
+        if let Err(err) = self.output.lock().force_flush() {
+            self.errors.handle(&err, None);
+        }
+    }
+}
+
+impl<O: TacitOutput, F: TacitFormatter> Logger<O, F> {
+    /// Evaluate property functions up front when both the formatter and the output need
+    /// the properties, so each function runs once per record and both see the same values.
+    fn evaluate_props(&self, record: &Record) -> Option<Vec<(String, Property)>> {
+        let has_functions = self
+            .default_props
+            .iter()
+            .any(|(_, prop)| matches!(prop, Property::Function(_)));
+        if !O::WANTS_FIELDS || !has_functions {
+            return None;
+        }
+
+        Some(
+            self.default_props
+                .iter()
+                .map(|(name, prop)| (name.clone(), Property::Static(prop.value(record))))
+                .collect(),
+        )
+    }
+
+    /// Render a record into `buffer` with the configured formatter.
+    fn format(
+        &self,
+        buffer: &mut Vec<u8>,
+        record: &Record,
+        props: &[(String, Property)],
+    ) -> std::io::Result<()> {
+        self.formatter.format(
+            buffer,
+            record,
+            &self.msg_prop,
+            props,
+            self.ignore_empty_props,
+        )
+    }
+
+    /// Describe a record for the output, collecting its fields only when the output wants them.
+    fn record_info(&self, record: &Record, props: &[(String, Property)]) -> RecordInfo {
+        let info = RecordInfo::from(record);
+        if O::WANTS_FIELDS {
+            info.with_fields(record, props, self.ignore_empty_props)
+        } else {
+            info
+        }
+    }
+
+    /// Render a record into `buffer`, then write it to the output. The output is only
+    /// locked for the write itself.
+    fn write(&self, buffer: &mut Vec<u8>, record: &Record) {
+        let evaluated = self.evaluate_props(record);
+        let props = evaluated.as_deref().unwrap_or(&self.default_props);
+
+        if let Err(err) = self.format(buffer, record, props) {
+            self.errors.handle(&err, None);
+            return;
+        }
+
+        let result = self
+            .output
+            .lock()
+            .write_entry(&self.record_info(record, props), buffer);
+        if let Err(err) = result {
+            self.errors.handle(&err, Some(buffer));
+        }
+    }
+}
+
+#[cfg(feature = "threaded")]
+impl<O: TacitOutput, F: TacitFormatter> Logger<O, F> {
+    /// Log how many entries were dropped since the last report, once the report interval
+    /// has passed or right away when `now` is set.
+    fn report_dropped(&self, writer: &AsyncWriter, now: bool) {
+        if let Some(dropped) = writer.due_report(now) {
+            self.send(
+                writer,
+                &Record::builder()
+                    .args(format_args!(
+                        "dropped {} log entries while the queue was full",
+                        dropped
+                    ))
+                    .level(log::Level::Warn)
+                    .target(module_path!())
+                    .build(),
+            );
+        }
+    }
+
+    /// Format a record and queue it for the writer thread.
+    fn send(&self, writer: &AsyncWriter, record: &Record) {
+        let evaluated = self.evaluate_props(record);
+        let props = evaluated.as_deref().unwrap_or(&self.default_props);
+
+        let mut buffer = Vec::new();
+        if let Err(err) = self.format(&mut buffer, record, props) {
+            self.errors.handle(&err, None);
+            return;
+        }
+
+        if let Err(rejected) = writer.send(self.record_info(record, props), buffer) {
+            let (info, entry) = *rejected;
+            let result = self.output.lock().write_entry(&info, &entry);
+            if let Err(err) = result {
+                self.errors.handle(&err, Some(&entry));
+            }
+        }
+    }
+}
+
+impl<O: TacitOutput, F: TacitFormatter> TacitLog for Logger<O, F> {
+    fn level_filter(&self) -> LevelFilter {
+        Logger::level_filter(self)
+    }
+
+    fn shutdown(&self) {
+        #[cfg(feature = "threaded")]
+        if let Some(writer) = &self.writer {
+            self.report_dropped(writer, true);
+            writer.shutdown();
+        }
+
+        if let Err(err) = self.output.lock().close() {
+            self.errors.handle(&err, None);
+        }
+    }
 }
 
 /// Describes all loggers provided by `tacit`
@@ -185,6 +393,89 @@ impl<O: TacitOutput, F: TacitFormatter> Logger<O, F> {
         self
     }
 
+    /// Set what happens when writing to the output fails, `ErrorPolicy::ReportOnce` by default.
+    pub fn set_error_policy(&mut self, policy: ErrorPolicy) {
+        self.errors = Arc::new(ErrorHandler::new(policy));
+    }
+
+    /// Set what happens when writing to the output fails, `ErrorPolicy::ReportOnce` by default.
+    /// Useful for chaining operations.
+    #[must_use]
+    pub fn with_error_policy(mut self, policy: ErrorPolicy) -> Self
+    where
+        Self: Sized,
+    {
+        self.set_error_policy(policy);
+        self
+    }
+
+    /// Write entries from a dedicated background thread instead of the thread doing the
+    /// logging. Formatted entries are queued, holding at most `capacity` at a time.
+    #[cfg(feature = "threaded")]
+    pub fn async_writes(&mut self, capacity: usize) {
+        self.async_capacity = Some(capacity);
+    }
+
+    /// Write entries from a dedicated background thread instead of the thread doing the
+    /// logging. Formatted entries are queued, holding at most `capacity` at a time. Useful
+    /// for chaining operations.
+    #[cfg(feature = "threaded")]
+    #[must_use]
+    pub fn with_async_writes(mut self, capacity: usize) -> Self
+    where
+        Self: Sized,
+    {
+        self.async_writes(capacity);
+        self
+    }
+
+    /// Set what happens to new entries while the async write queue is full.
+    #[cfg(feature = "threaded")]
+    pub fn set_backpressure(&mut self, backpressure: Backpressure) {
+        self.backpressure = backpressure;
+    }
+
+    /// Set what happens to new entries while the async write queue is full. Useful for
+    /// chaining operations.
+    #[cfg(feature = "threaded")]
+    #[must_use]
+    pub fn with_backpressure(mut self, backpressure: Backpressure) -> Self
+    where
+        Self: Sized,
+    {
+        self.set_backpressure(backpressure);
+        self
+    }
+
+    /// Set how often the number of dropped entries is written to the log, if any were dropped.
+    /// Entries dropped since the last report are also reported when the logger is flushed or
+    /// shut down.
+    #[cfg(feature = "threaded")]
+    pub fn set_drop_report_interval(&mut self, interval: Duration) {
+        self.drop_report_interval = interval;
+    }
+
+    /// Set how often the number of dropped entries is written to the log, if any were dropped.
+    /// Useful for chaining operations.
+    #[cfg(feature = "threaded")]
+    #[must_use]
+    pub fn with_drop_report_interval(mut self, interval: Duration) -> Self
+    where
+        Self: Sized,
+    {
+        self.set_drop_report_interval(interval);
+        self
+    }
+
+    /// Counter of entries dropped due to backpressure, which can be kept to monitor the
+    /// `Logger` after it has been registered.
+    #[cfg(feature = "threaded")]
+    pub fn dropped_entries(&self) -> DropCounter {
+        self.dropped.clone()
+    }
+}
+
+impl<O: 'static + TacitOutput, F: TacitFormatter> Logger<O, F> {
     /// Prepare the `Logger` for logging operations
     pub(crate) fn finalize(mut self) -> Self {
         self.sorted_module_levels = self
@@ -198,6 +489,284 @@ impl<O: TacitOutput, F: TacitFormatter> Logger<O, F> {
         self.sorted_module_levels
             .sort_by_key(|(name, _level)| name.len().wrapping_neg());
 
+        {
+            let mut output = self.output.lock();
+            output.set_error_reporter(ErrorReporter::new(self.errors.clone()));
+            self.formatter
+                .set_terminal(&|level| output.is_terminal(level));
+        }
+
+        #[cfg(feature = "threaded")]
+        if let Some(capacity) = self.async_capacity {
+            self.writer = Some(AsyncWriter::new(
+                self.output.clone(),
+                capacity,
+                self.backpressure,
+                self.dropped.clone(),
+                self.drop_report_interval,
+                self.errors.clone(),
+            ));
+        }
+
         self
     }
 }
+
+#[cfg(all(test, feature = "threaded"))]
+mod tests {
+    use super::*;
+    use crate::{test_util::SharedOutput, SimpleFormatter};
+    use std::io::Write;
+
+    fn log_messages<L: Log>(logger: &L, levels: &[log::Level]) {
+        for (n, level) in levels.iter().enumerate() {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("entry {}", n))
+                    .level(*level)
+                    .build(),
+            );
+        }
+    }
+
+    /// Output that blocks writing until the gate is released.
+    #[derive(Clone, Default)]
+    struct GatedOutput {
+        gate: Arc<Mutex<()>>,
+        inner: SharedOutput,
+    }
+
+    impl TacitOutput for GatedOutput {}
+
+    impl Write for GatedOutput {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let _gate = self.gate.lock();
+            self.inner.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Log with a full queue, the first entry is held up by the writer thread while the
+    /// rest compete for the two queue slots.
+    fn log_with_backpressure(backpressure: Backpressure, levels: &[log::Level]) -> (String, u64) {
+        let output = GatedOutput::default();
+        let gate = output.gate.lock();
+
+        let logger = Logger::new(output.clone(), SimpleFormatter::default())
+            .with_async_writes(2)
+            .with_backpressure(backpressure)
+            .finalize();
+        let dropped = logger.dropped_entries();
+
+        log_messages(&logger, &levels[..1]);
+        while !logger.writer.as_ref().unwrap().is_idle() {
+            std::thread::yield_now();
+        }
+        for (n, level) in levels.iter().enumerate().skip(1) {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("entry {}", n))
+                    .level(*level)
+                    .build(),
+            );
+        }
+
+        drop(gate);
+        drop(logger);
+
+        let written = output.inner.contents();
+        (written, dropped.count())
+    }
+
+    #[test]
+    fn async_writes_everything() {
+        let output = SharedOutput::default();
+        let logger = Logger::new(output.clone(), SimpleFormatter::default())
+            .with_async_writes(2)
+            .finalize();
+
+        log_messages(&logger, &[log::Level::Info; 10]);
+        drop(logger);
+
+        let written = output.contents();
+        let expected = (0..10)
+            .map(|n| format!("msg=\"entry {}\"\n", n))
+            .collect::<String>();
+        assert_eq!(written, expected);
+    }
+
+    #[test]
+    fn drops_oldest() {
+        let (written, dropped) =
+            log_with_backpressure(Backpressure::DropOldest, &[log::Level::Info; 5]);
+
+        assert_eq!(
+            written,
+            "msg=\"entry 0\"\nmsg=\"entry 3\"\nmsg=\"entry 4\"\n"
+        );
+        assert_eq!(dropped, 2);
+    }
+
+    #[test]
+    fn drops_newest() {
+        let (written, dropped) =
+            log_with_backpressure(Backpressure::DropNewest, &[log::Level::Info; 5]);
+
+        assert_eq!(
+            written,
+            "msg=\"entry 0\"\nmsg=\"entry 1\"\nmsg=\"entry 2\"\n"
+        );
+        assert_eq!(dropped, 2);
+    }
+
+    #[test]
+    fn drops_below_level() {
+        use log::Level::{Debug, Info, Warn};
+
+        let (written, dropped) = log_with_backpressure(
+            Backpressure::DropBelow(Warn),
+            &[Info, Info, Debug, Debug, Info],
+        );
+
+        assert_eq!(
+            written,
+            "msg=\"entry 0\"\nmsg=\"entry 1\"\nmsg=\"entry 2\"\n"
+        );
+        assert_eq!(dropped, 2);
+    }
+
+    #[test]
+    fn reports_dropped_entries() {
+        let output = SharedOutput::default();
+        let logger = Logger::new(output.clone(), SimpleFormatter::default())
+            .with_async_writes(2)
+            .with_drop_report_interval(Duration::ZERO)
+            .finalize();
+
+        logger.dropped.increment();
+        log_messages(&logger, &[log::Level::Info]);
+        drop(logger);
+
+        let written = output.contents();
+        assert_eq!(
+            written,
+            "msg=\"dropped 1 log entries while the queue was full\"\nmsg=\"entry 0\"\n"
+        );
+    }
+
+    #[test]
+    fn flush_waits_for_queue() {
+        let output = GatedOutput::default();
+        let gate = output.gate.lock();
+
+        let logger = Logger::new(output.clone(), SimpleFormatter::default())
+            .with_async_writes(4)
+            .finalize();
+        log_messages(&logger, &[log::Level::Info; 3]);
+
+        let flusher = std::thread::spawn({
+            let output = output.inner.clone();
+            move || {
+                logger.flush();
+                output.contents()
+            }
+        });
+        std::thread::sleep(Duration::from_millis(50));
+        drop(gate);
+
+        assert_eq!(
+            flusher.join().unwrap(),
+            "msg=\"entry 0\"\nmsg=\"entry 1\"\nmsg=\"entry 2\"\n"
+        );
+    }
+
+    #[test]
+    fn shutdown_writes_directly() {
+        let output = GatedOutput::default();
+        let gate = output.gate.lock();
+
+        let logger = Logger::new(output.clone(), SimpleFormatter::default())
+            .with_async_writes(4)
+            .finalize();
+        log_messages(&logger, &[log::Level::Info; 2]);
+
+        drop(gate);
+        logger.shutdown();
+        assert_eq!(
+            output.inner.contents(),
+            "msg=\"entry 0\"\nmsg=\"entry 1\"\n"
+        );
+
+        log_messages(&logger, &[log::Level::Info]);
+        assert_eq!(
+            output.inner.contents(),
+            "msg=\"entry 0\"\nmsg=\"entry 1\"\nmsg=\"entry 0\"\n"
+        );
+    }
+
+    /// Output logging through its logger while writing, as the writer thread.
+    #[derive(Clone, Default)]
+    struct ChattyOutput {
+        logger: Arc<Mutex<Option<Arc<dyn Log + Send + Sync>>>>,
+        inner: SharedOutput,
+    }
+
+    impl TacitOutput for ChattyOutput {}
+
+    impl Write for ChattyOutput {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if buf.starts_with(b"msg=outer") {
+                let logger = self.logger.lock().clone().unwrap();
+                for n in 0..2 {
+                    logger.log(&Record::builder().args(format_args!("inner{}", n)).build());
+                }
+            }
+            self.inner.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn drops_entries_the_writer_thread_can_not_queue() {
+        let output = ChattyOutput::default();
+        let logger = Arc::new(
+            Logger::new(output.clone(), SimpleFormatter::default())
+                .with_async_writes(1)
+                .finalize(),
+        );
+        *output.logger.lock() = Some(logger.clone());
+
+        logger.log(&Record::builder().args(format_args!("outer")).build());
+        logger.flush();
+
+        // the first inner entry fits in the emptied queue, the second one would block
+        assert_eq!(output.inner.contents(), "msg=outer\nmsg=inner0\n");
+        assert_eq!(logger.dropped_entries().count(), 1);
+        output.logger.lock().take();
+    }
+
+    #[test]
+    fn reports_dropped_entries_on_flush() {
+        let output = SharedOutput::default();
+        let logger = Logger::new(output.clone(), SimpleFormatter::default())
+            .with_async_writes(2)
+            .with_drop_report_interval(Duration::from_secs(3600))
+            .finalize();
+
+        logger.dropped.increment();
+        log_messages(&logger, &[log::Level::Info]);
+        // nothing is logged after the burst, the report is not left waiting for it
+        logger.flush();
+
+        assert_eq!(
+            output.contents(),
+            "msg=\"entry 0\"\nmsg=\"dropped 1 log entries while the queue was full\"\n"
+        );
+    }
+}
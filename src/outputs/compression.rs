@@ -0,0 +1,214 @@
+//! # Compression
+//! Compresses rotated log files on a background thread so the active output
+//! is never held up by it.
+
+use flate2::write::GzEncoder;
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    thread::JoinHandle,
+};
+
+/// Compression applied to files once they have been rotated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+/// Extensions of all compressed archive formats.
+const EXTENSIONS: &[&str] = &[".gz", ".zst"];
+
+impl Compression {
+    /// Extension appended to compressed archives.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::None => "",
+            Self::Gzip => ".gz",
+            #[cfg(feature = "zstd")]
+            Self::Zstd => ".zst",
+        }
+    }
+
+    /// Split a file name into the name it had before compression, and its compression
+    /// extension.
+    pub(crate) fn split_extension(name: &str) -> (&str, &'static str) {
+        EXTENSIONS
+            .iter()
+            .find_map(|ext| name.strip_suffix(ext).map(|stem| (stem, *ext)))
+            .unwrap_or((name, ""))
+    }
+
+    fn compress(&self, path: &Path) -> io::Result<()> {
+        if *self == Self::None {
+            return Ok(());
+        }
+
+        let target = append(path, self.extension());
+        let partial = append(&target, ".partial");
+
+        let result = self
+            .encode(path, &partial)
+            .and_then(|()| fs::rename(&partial, &target));
+        if result.is_err() {
+            // pruning ignores partial archives, so nothing else would remove it
+            let _ = fs::remove_file(&partial);
+        }
+        result?;
+
+        fs::remove_file(path)
+    }
+
+    /// Write the compressed contents of `path` to `partial`.
+    fn encode(&self, path: &Path, partial: &Path) -> io::Result<()> {
+        let mut input = BufReader::new(File::open(path)?);
+        let output = BufWriter::new(File::create(partial)?);
+
+        match self {
+            Self::None => unreachable!("uncompressed archives are left as is"),
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(output, flate2::Compression::default());
+                io::copy(&mut input, &mut encoder)?;
+                encoder.finish()?.flush()
+            }
+            #[cfg(feature = "zstd")]
+            Self::Zstd => {
+                let mut encoder = zstd::Encoder::new(output, 0)?;
+                io::copy(&mut input, &mut encoder)?;
+                encoder.finish()?.flush()
+            }
+        }
+    }
+}
+
+fn append(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+/// Tracks background compression jobs.
+#[derive(Default)]
+pub(crate) struct Compressor {
+    compression: Compression,
+    pending: Vec<JoinHandle<()>>,
+}
+
+impl Compressor {
+    pub(crate) fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    /// Compress the file at `path` in the background, replacing it once finished.
+    pub(crate) fn compress(&mut self, path: PathBuf) {
+        let _ = self.compress_and_prune(path, || Ok(()));
+    }
+
+    /// Compress the file at `path` in the background, then call `prune`, so that retention
+    /// sees the compressed size of the archive. Without compression `prune` is called right
+    /// away instead.
+    pub(crate) fn compress_and_prune<F>(&mut self, path: PathBuf, prune: F) -> io::Result<()>
+    where
+        F: FnOnce() -> io::Result<()> + Send + 'static,
+    {
+        if self.compression == Compression::None {
+            return prune();
+        }
+
+        self.pending.retain(|job| !job.is_finished());
+
+        let compression = self.compression;
+        self.pending.push(std::thread::spawn(move || {
+            match compression.compress(&path) {
+                Ok(()) => {}
+                // the archive was removed by retention before it could be compressed
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => eprintln!("tacit: unable to compress {}: {}", path.display(), err),
+            }
+            if let Err(err) = prune() {
+                eprintln!(
+                    "tacit: unable to prune archives of {}: {}",
+                    path.display(),
+                    err
+                );
+            }
+        }));
+        Ok(())
+    }
+
+    /// Block until all background compression has finished.
+    pub(crate) fn wait(&mut self) {
+        for job in self.pending.drain(..) {
+            let _ = job.join();
+        }
+    }
+}
+
+impl Drop for Compressor {
+    fn drop(&mut self) {
+        self.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tacit-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn compresses_with_gzip() {
+        let dir = temp_dir("compress-gzip");
+        let path = dir.join("app.log.1");
+        fs::write(&path, "archived\n").unwrap();
+
+        Compression::Gzip.compress(&path).unwrap();
+
+        let mut contents = String::new();
+        flate2::read::GzDecoder::new(File::open(dir.join("app.log.1.gz")).unwrap())
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "archived\n");
+        assert!(!path.exists());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn compresses_with_zstd() {
+        let dir = temp_dir("compress-zstd");
+        let path = dir.join("app.log.1");
+        fs::write(&path, "archived\n").unwrap();
+
+        Compression::Zstd.compress(&path).unwrap();
+
+        let contents = zstd::decode_all(File::open(dir.join("app.log.1.zst")).unwrap()).unwrap();
+        assert_eq!(contents, b"archived\n");
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn removes_partial_archives_on_failure() {
+        let dir = temp_dir("compress-failure");
+        // a directory opens like a file, but fails once it is read
+        let path = dir.join("app.log.1");
+        fs::create_dir(&path).unwrap();
+
+        assert!(Compression::Gzip.compress(&path).is_err());
+
+        let names: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, vec!["app.log.1"]);
+    }
+}
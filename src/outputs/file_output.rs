@@ -0,0 +1,234 @@
+//! # File Output
+//! Append all output to a file on disk.
+//!
+//! The file is opened lazily on the first write, creating any missing parent
+//! directories. When an external tool such as `logrotate` moves the file away,
+//! a `ReopenHandle` can be used to have the output start a fresh file at the
+//! configured path. Tools using `copytruncate` need no coordination since the
+//! file is always opened in append mode.
+
+use super::TacitOutput;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// Default path used when no other is provided.
+const DEFAULT_PATH: &str = "tacit.log";
+
+pub struct FileOutput {
+    path: PathBuf,
+    create_dirs: bool,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    mode: Option<u32>,
+    file: Option<File>,
+    reopen: Arc<AtomicBool>,
+}
+
+impl Default for FileOutput {
+    fn default() -> Self {
+        Self::new(DEFAULT_PATH)
+    }
+}
+
+impl FileOutput {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            create_dirs: true,
+            mode: None,
+            file: None,
+            reopen: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Path of the file being written to.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Create missing parent directories when opening the file, enabled by default.
+    pub fn set_create_dirs(&mut self, create_dirs: bool) {
+        self.create_dirs = create_dirs;
+    }
+
+    /// Create missing parent directories when opening the file. Useful for chaining operations.
+    #[must_use]
+    pub fn with_create_dirs(mut self, create_dirs: bool) -> Self {
+        self.set_create_dirs(create_dirs);
+        self
+    }
+
+    /// Set the permissions (e.g. `0o640`) applied to the file when it is opened.
+    /// Only has an effect on unix platforms.
+    pub fn set_mode(&mut self, mode: u32) {
+        self.mode = Some(mode);
+    }
+
+    /// Set the permissions (e.g. `0o640`) applied to the file when it is opened.
+    /// Only has an effect on unix platforms. Useful for chaining operations.
+    #[must_use]
+    pub fn with_mode(mut self, mode: u32) -> Self {
+        self.set_mode(mode);
+        self
+    }
+
+    /// Get a handle that can request the file be reopened from another thread,
+    /// e.g. from a `SIGHUP` handler after `logrotate` has moved the file.
+    pub fn reopen_handle(&self) -> ReopenHandle {
+        ReopenHandle(self.reopen.clone())
+    }
+
+    /// Close the current file handle and open the configured path again.
+    pub fn reopen(&mut self) -> io::Result<()> {
+        self.close()?;
+        self.file().map(|_| ())
+    }
+
+    /// Close the current file handle, the next write will open `path` instead.
+    pub(crate) fn set_path<P: Into<PathBuf>>(&mut self, path: P) -> io::Result<()> {
+        self.close()?;
+        self.path = path.into();
+        Ok(())
+    }
+
+    /// Flush and close the current file handle, the next write will open it again.
+    pub(crate) fn close(&mut self) -> io::Result<()> {
+        self.reopen.store(false, Ordering::SeqCst);
+        match self.file.take() {
+            Some(mut file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Get the open file handle, opening it as needed.
+    pub(crate) fn file(&mut self) -> io::Result<&mut File> {
+        if self.reopen.swap(false, Ordering::SeqCst) {
+            self.file = None;
+        }
+
+        if self.file.is_none() {
+            self.file = Some(self.open()?);
+        }
+
+        Ok(self.file.as_mut().expect("file handle was just opened"))
+    }
+
+    fn open(&self) -> io::Result<File> {
+        if self.create_dirs {
+            if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                fs::create_dir_all(parent)?;
+            }
+        }
+
+        let mut options = OpenOptions::new();
+        options.create(true).append(true);
+
+        #[cfg(unix)]
+        if let Some(mode) = self.mode {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(mode);
+        }
+
+        let file = options.open(&self.path)?;
+
+        #[cfg(unix)]
+        if let Some(mode) = self.mode {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(fs::Permissions::from_mode(mode))?;
+        }
+
+        Ok(file)
+    }
+}
+
+impl TacitOutput for FileOutput {}
+
+impl Write for FileOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file()?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Requests that a `FileOutput` reopen its file before the next write.
+#[derive(Clone)]
+pub struct ReopenHandle(Arc<AtomicBool>);
+
+impl ReopenHandle {
+    pub fn reopen(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tacit-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("nested").join("test.log")
+    }
+
+    #[test]
+    fn appends_and_creates_dirs() {
+        let path = temp_path("file-append");
+        assert!(!path.parent().unwrap().exists());
+
+        let mut output = FileOutput::new(&path).with_create_dirs(false);
+        let err = writeln!(output, "lost").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+        let mut output = FileOutput::new(&path);
+        writeln!(output, "created").unwrap();
+        output.flush().unwrap();
+
+        let mut output = FileOutput::new(&path);
+        writeln!(output, "appended").unwrap();
+        output.flush().unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "created\nappended\n");
+    }
+
+    #[test]
+    fn reopens_after_move() {
+        let path = temp_path("file-reopen");
+        let moved = path.with_extension("log.1");
+
+        let mut output = FileOutput::new(&path);
+        let handle = output.reopen_handle();
+        writeln!(output, "first").unwrap();
+
+        fs::rename(&path, &moved).unwrap();
+        handle.reopen();
+        writeln!(output, "second").unwrap();
+
+        assert_eq!(fs::read_to_string(&moved).unwrap(), "first\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sets_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = temp_path("file-mode");
+        let mut output = FileOutput::new(&path).with_mode(0o600);
+        writeln!(output, "secret").unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
@@ -0,0 +1,343 @@
+//! # GELF Outputs
+//! Send entries from the `GelfFormatter` to Graylog, or any other GELF input.
+//!
+//! `GelfUdpOutput` sends every message as a datagram, optionally compressed with
+//! zlib or gzip. Messages larger than the chunk size are split into at most 128
+//! GELF chunks, larger messages are dropped. `GelfTcpOutput` streams messages
+//! terminated by a null byte, reconnecting and buffering like the `TcpOutput`.
+//!
+//! Trailing newlines are removed from entries before they are sent.
+
+use super::{TacitOutput, TcpOutput};
+use crate::ErrorReporter;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use std::{
+    io::{self, Write},
+    net::{ToSocketAddrs, UdpSocket},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Default address of a GELF UDP or TCP input.
+const DEFAULT_ADDRESS: &str = "127.0.0.1:12201";
+
+/// Chunk size recommended for messages crossing the internet.
+const DEFAULT_CHUNK_SIZE: usize = 1420;
+
+/// Magic bytes, message id, sequence number and sequence count.
+const CHUNK_HEADER: usize = 12;
+const CHUNK_MAGIC: [u8; 2] = [0x1e, 0x0f];
+const MAX_CHUNKS: usize = 128;
+
+/// Compression applied to GELF datagrams.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GelfCompression {
+    #[default]
+    None,
+    Zlib,
+    Gzip,
+}
+
+impl GelfCompression {
+    fn compress(&self, message: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Self::None => Ok(message.to_vec()),
+            Self::Zlib => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(message)?;
+                encoder.finish()
+            }
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(message)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// The entry without its trailing newline.
+fn message(entry: &[u8]) -> &[u8] {
+    entry.strip_suffix(b"\n").unwrap_or(entry)
+}
+
+pub struct GelfUdpOutput {
+    address: String,
+    chunk_size: usize,
+    compression: GelfCompression,
+    socket: Option<UdpSocket>,
+    sent: u64,
+}
+
+impl Default for GelfUdpOutput {
+    fn default() -> Self {
+        Self::new(DEFAULT_ADDRESS)
+    }
+}
+
+impl GelfUdpOutput {
+    /// Send messages to the GELF UDP input at `address`, e.g. `graylog.example.com:12201`.
+    pub fn new<S: Into<String>>(address: S) -> Self {
+        Self {
+            address: address.into(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            compression: GelfCompression::default(),
+            socket: None,
+            sent: 0,
+        }
+    }
+
+    /// Set the largest datagram, including chunk headers, 1420 bytes by default. Graylog
+    /// recommends 8192 bytes within a local network.
+    pub fn set_chunk_size(&mut self, chunk_size: usize) {
+        self.chunk_size = chunk_size.max(CHUNK_HEADER + 1);
+    }
+
+    /// Set the largest datagram, including chunk headers, 1420 bytes by default. Useful for
+    /// chaining operations.
+    #[must_use]
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.set_chunk_size(chunk_size);
+        self
+    }
+
+    /// Set the compression applied to messages, none by default.
+    pub fn set_compression(&mut self, compression: GelfCompression) {
+        self.compression = compression;
+    }
+
+    /// Set the compression applied to messages, none by default. Useful for chaining
+    /// operations.
+    #[must_use]
+    pub fn with_compression(mut self, compression: GelfCompression) -> Self {
+        self.set_compression(compression);
+        self
+    }
+
+    fn connect(&self) -> io::Result<UdpSocket> {
+        let address = self
+            .address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::AddrNotAvailable))?;
+        let local = if address.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(address)?;
+        Ok(socket)
+    }
+
+    /// An id for the next chunked message, unique enough for the input to tell messages
+    /// apart while reassembling them.
+    fn message_id(&mut self) -> [u8; 8] {
+        self.sent = self.sent.wrapping_add(1);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos() as u64)
+            .unwrap_or_default();
+        let id = (nanos ^ u64::from(std::process::id()).rotate_left(32))
+            .wrapping_add(self.sent.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        id.to_be_bytes()
+    }
+
+    /// Datagrams for a message, chunked when it is larger than the chunk size.
+    fn datagrams(&mut self, message: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+        if message.len() <= self.chunk_size {
+            return Ok(vec![message.to_vec()]);
+        }
+
+        let room = self.chunk_size - CHUNK_HEADER;
+        let count = message.len().div_ceil(room);
+        if count > MAX_CHUNKS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "GELF message of {} bytes needs more than {} chunks",
+                    message.len(),
+                    MAX_CHUNKS
+                ),
+            ));
+        }
+
+        let id = self.message_id();
+        Ok(message
+            .chunks(room)
+            .enumerate()
+            .map(|(sequence, chunk)| {
+                let mut datagram = Vec::with_capacity(CHUNK_HEADER + chunk.len());
+                datagram.extend_from_slice(&CHUNK_MAGIC);
+                datagram.extend_from_slice(&id);
+                datagram.push(sequence as u8);
+                datagram.push(count as u8);
+                datagram.extend_from_slice(chunk);
+                datagram
+            })
+            .collect())
+    }
+}
+
+impl TacitOutput for GelfUdpOutput {}
+
+impl Write for GelfUdpOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.socket.is_none() {
+            self.socket = Some(self.connect()?);
+        }
+
+        let compressed = self.compression.compress(message(buf))?;
+        for datagram in self.datagrams(&compressed)? {
+            let result = match &self.socket {
+                Some(socket) => socket.send(&datagram),
+                None => break,
+            };
+            match result {
+                Ok(_) => {}
+                // nobody listening, drop the message
+                Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => break,
+                Err(err) => {
+                    self.socket = None;
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Streams null byte terminated GELF messages over TCP. Create it from a configured
+/// `TcpOutput` to change its timeouts, backoff, buffering or TLS settings.
+pub struct GelfTcpOutput {
+    inner: TcpOutput,
+}
+
+impl Default for GelfTcpOutput {
+    fn default() -> Self {
+        Self::new(DEFAULT_ADDRESS)
+    }
+}
+
+impl GelfTcpOutput {
+    /// Stream messages to the GELF TCP input at `address`, e.g. `graylog.example.com:12201`.
+    pub fn new<S: Into<String>>(address: S) -> Self {
+        Self {
+            inner: TcpOutput::new(address),
+        }
+    }
+}
+
+impl From<TcpOutput> for GelfTcpOutput {
+    fn from(inner: TcpOutput) -> Self {
+        Self { inner }
+    }
+}
+
+impl TacitOutput for GelfTcpOutput {
+    fn set_error_reporter(&mut self, reporter: ErrorReporter) {
+        self.inner.set_error_reporter(reporter);
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.inner.close()
+    }
+}
+
+impl Write for GelfTcpOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let message = message(buf);
+        let mut framed = Vec::with_capacity(message.len() + 1);
+        framed.extend_from_slice(message);
+        framed.push(0);
+        self.inner.write_all(&framed)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::{io::Read, net::TcpListener};
+
+    #[test]
+    fn chunks_compressed_messages() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut output = GelfUdpOutput::new(server.local_addr().unwrap().to_string())
+            .with_chunk_size(32)
+            .with_compression(GelfCompression::Gzip);
+
+        let message = (0..200).map(|i| format!("{:x}", i)).collect::<String>();
+        output
+            .write_all(format!("{{\"short_message\":\"{}\"}}\n", message).as_bytes())
+            .unwrap();
+
+        let mut buf = [0; 64];
+        let len = server.recv(&mut buf).unwrap();
+        assert!(len <= 32);
+        assert_eq!(&buf[..2], &CHUNK_MAGIC);
+        let (id, count) = (buf[2..10].to_vec(), buf[11] as usize);
+        assert!(count > 1);
+
+        let mut chunks = vec![Vec::new(); count];
+        chunks[buf[10] as usize] = buf[CHUNK_HEADER..len].to_vec();
+        for _ in 1..count {
+            let len = server.recv(&mut buf).unwrap();
+            assert_eq!(&buf[2..10], &id[..]);
+            assert_eq!(buf[11] as usize, count);
+            chunks[buf[10] as usize] = buf[CHUNK_HEADER..len].to_vec();
+        }
+
+        let mut decoded = String::new();
+        GzDecoder::new(&chunks.concat()[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, format!("{{\"short_message\":\"{}\"}}", message));
+    }
+
+    #[test]
+    fn rejects_messages_needing_too_many_chunks() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut output =
+            GelfUdpOutput::new(server.local_addr().unwrap().to_string()).with_chunk_size(13);
+
+        assert!(output.write_all(&[b'a'; MAX_CHUNKS + 1]).is_err());
+        output.write_all(b"{}\n").unwrap();
+
+        let mut buf = [0; 16];
+        let len = server.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"{}");
+    }
+
+    #[test]
+    fn frames_tcp_messages_with_null_bytes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut output = GelfTcpOutput::new(listener.local_addr().unwrap().to_string());
+
+        output.write_all(b"{\"short_message\":\"one\"}\n").unwrap();
+        output.write_all(b"{\"short_message\":\"two\"}\n").unwrap();
+        output.close().unwrap();
+        drop(output);
+
+        let mut received = Vec::new();
+        listener
+            .accept()
+            .unwrap()
+            .0
+            .read_to_end(&mut received)
+            .unwrap();
+        assert_eq!(
+            received,
+            b"{\"short_message\":\"one\"}\0{\"short_message\":\"two\"}\0"
+        );
+    }
+}
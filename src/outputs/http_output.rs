@@ -0,0 +1,1018 @@
+//! # HTTP Output
+//! POST batches of entries to an ingestion endpoint, as newline delimited JSON or
+//! a JSON array, e.g. entries from the `JsonFormatter`.
+//!
+//! Entries are collected by a background thread, started with a copy of the
+//! settings on the first entry, and sent once a batch holds enough entries or
+//! bytes, or its oldest entry is old enough. Server errors and connection failures
+//! are retried with exponential backoff, other responses are final. When a spool
+//! directory is set, batches that could not be delivered are written to it and
+//! sent again, oldest first, once the endpoint is back. Flushing the logger, e.g.
+//! with `log::logger().flush()`, shutting it down or dropping the output sends
+//! whatever is left, without waiting for the batch to fill up. A plain `flush`, as
+//! done by the async writer whenever its queue runs empty, leaves batches to fill up.
+//!
+//! Failures of the background thread, such as batches that could not be sent, are
+//! handled by the logger's `ErrorPolicy`. Records logged by the background thread, or by the HTTP client it uses, are
+//! left out, so sending a batch never produces entries of its own.
+
+use super::TacitOutput;
+use crate::ErrorReporter;
+use log::Metadata;
+use parking_lot::{Condvar, Mutex};
+use reqwest::blocking::Client;
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Default endpoint used when no other is provided.
+const DEFAULT_URL: &str = "http://127.0.0.1:8080/";
+const DEFAULT_MAX_BATCH_ENTRIES: usize = 100;
+const DEFAULT_MAX_BATCH_BYTES: usize = 1024 * 1024;
+const DEFAULT_MAX_BATCH_AGE: Duration = Duration::from_secs(1);
+const DEFAULT_RETRIES: u32 = 3;
+const DEFAULT_MIN_BACKOFF: Duration = Duration::from_millis(100);
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(10);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_MAX_SPOOL: u64 = 64 * 1024 * 1024;
+const DEFAULT_MAX_BUFFER: usize = 8 * 1024 * 1024;
+
+const SPOOL_EXTENSION: &str = "batch";
+
+/// Name of the background thread sending batches.
+const SENDER_THREAD: &str = "tacit-http";
+
+/// Targets of the HTTP client and the libraries below it.
+const CLIENT_TARGETS: &[&str] = &["reqwest", "hyper", "hyper_util", "h2", "rustls", "want"];
+
+/// Whether a record comes from sending batches, either logged on the background thread
+/// or by the HTTP client.
+pub(crate) fn is_sender_record(metadata: &Metadata) -> bool {
+    let target = metadata.target();
+    thread::current().name() == Some(SENDER_THREAD)
+        || CLIENT_TARGETS.iter().any(|client| {
+            target
+                .strip_prefix(client)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+        })
+}
+
+/// Turns batches of entries into request bodies, letting outputs built on the
+/// `HttpOutput` batch entries of their own.
+pub(crate) trait Encoder: Send + 'static {
+    type Entry: Send + 'static;
+
+    /// Size of an entry, counted against the batch byte limit.
+    fn entry_len(entry: &Self::Entry) -> usize;
+
+    /// The rendered entry, handed to the error policy when it could not be sent.
+    fn entry_line(entry: &Self::Entry) -> &[u8];
+
+    fn content_type(&self) -> &'static str;
+
+    fn encode(&self, entries: &[Self::Entry]) -> io::Result<Vec<u8>>;
+}
+
+/// How the entries of a batch are combined into a request body.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HttpBatchFormat {
+    /// One entry per line, sent as `application/x-ndjson`.
+    #[default]
+    Ndjson,
+    /// Entries as the elements of a JSON array, sent as `application/json`.
+    JsonArray,
+}
+
+impl Encoder for HttpBatchFormat {
+    type Entry = Vec<u8>;
+
+    fn entry_len(entry: &Vec<u8>) -> usize {
+        entry.len()
+    }
+
+    fn entry_line(entry: &Vec<u8>) -> &[u8] {
+        entry
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            Self::Ndjson => "application/x-ndjson",
+            Self::JsonArray => "application/json",
+        }
+    }
+
+    fn encode(&self, entries: &[Vec<u8>]) -> io::Result<Vec<u8>> {
+        let len = entries.iter().map(|entry| entry.len() + 1).sum::<usize>() + 1;
+        let mut body = Vec::with_capacity(len);
+        match self {
+            Self::Ndjson => {
+                for entry in entries {
+                    body.extend_from_slice(entry);
+                    body.push(b'\n');
+                }
+            }
+            Self::JsonArray => {
+                body.push(b'[');
+                for (index, entry) in entries.iter().enumerate() {
+                    if index > 0 {
+                        body.push(b',');
+                    }
+                    body.extend_from_slice(entry);
+                }
+                body.push(b']');
+            }
+        }
+        Ok(body)
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Settings {
+    url: String,
+    headers: Vec<(String, String)>,
+    max_batch_entries: usize,
+    max_batch_bytes: usize,
+    max_batch_age: Duration,
+    retries: u32,
+    min_backoff: Duration,
+    max_backoff: Duration,
+    timeout: Duration,
+    spool: Option<PathBuf>,
+    max_spool: u64,
+    max_buffer: usize,
+}
+
+impl Settings {
+    fn new(url: String) -> Self {
+        Self {
+            url,
+            headers: Vec::new(),
+            max_batch_entries: DEFAULT_MAX_BATCH_ENTRIES,
+            max_batch_bytes: DEFAULT_MAX_BATCH_BYTES,
+            max_batch_age: DEFAULT_MAX_BATCH_AGE,
+            retries: DEFAULT_RETRIES,
+            min_backoff: DEFAULT_MIN_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            timeout: DEFAULT_TIMEOUT,
+            spool: None,
+            max_spool: DEFAULT_MAX_SPOOL,
+            max_buffer: DEFAULT_MAX_BUFFER,
+        }
+    }
+}
+
+struct Pending<T> {
+    entries: Vec<T>,
+    bytes: usize,
+    since: Option<Instant>,
+    closed: bool,
+    /// Entries queued so far.
+    pushed: u64,
+    /// Entries taken by the background thread so far.
+    taken: u64,
+    /// Entries sent, spooled or discarded so far.
+    done: u64,
+    /// Entries discarded since the last report, while the buffer was full.
+    discarded: u64,
+    /// Entries up to which a flush is waiting, sent regardless of the batch limits.
+    flush_until: u64,
+}
+
+struct Shared<T> {
+    pending: Mutex<Pending<T>>,
+    wake: Condvar,
+    done: Condvar,
+}
+
+/// Collects entries for a background thread that sends them in batches.
+pub(crate) struct Batcher<E: Encoder> {
+    settings: Settings,
+    encoder: Option<E>,
+    shared: Arc<Shared<E::Entry>>,
+    sender: Option<JoinHandle<()>>,
+    errors: ErrorReporter,
+}
+
+impl<E: Encoder> Batcher<E> {
+    pub(crate) fn new(settings: Settings, encoder: E) -> Self {
+        Self {
+            settings,
+            encoder: Some(encoder),
+            shared: Arc::new(Shared {
+                pending: Mutex::new(Pending {
+                    entries: Vec::new(),
+                    bytes: 0,
+                    since: None,
+                    closed: false,
+                    pushed: 0,
+                    taken: 0,
+                    done: 0,
+                    discarded: 0,
+                    flush_until: 0,
+                }),
+                wake: Condvar::new(),
+                done: Condvar::new(),
+            }),
+            sender: None,
+            errors: ErrorReporter::default(),
+        }
+    }
+
+    /// Report failures of the background thread to `errors`.
+    pub(crate) fn set_error_reporter(&mut self, errors: ErrorReporter) {
+        self.errors = errors;
+    }
+
+    /// The encoder, until the background thread has taken it over.
+    pub(crate) fn encoder_mut(&mut self) -> Option<&mut E> {
+        self.encoder.as_mut()
+    }
+
+    /// Queue an entry, starting the background thread on the first one.
+    pub(crate) fn push(&mut self, entry: E::Entry) -> io::Result<()> {
+        if let Some(encoder) = self.encoder.take() {
+            let settings = self.settings.clone();
+            let shared = self.shared.clone();
+            let errors = self.errors.clone();
+            self.sender = Some(
+                thread::Builder::new()
+                    .name(String::from(SENDER_THREAD))
+                    .spawn(move || {
+                        // built here rather than by the caller, which may hold the output
+                        // lock, since building the client logs and starts a runtime
+                        let client = match Client::builder().timeout(settings.timeout).build() {
+                            Ok(client) => client,
+                            Err(err) => {
+                                errors.report(&io::Error::other(err), None);
+                                return;
+                            }
+                        };
+                        Sender {
+                            settings,
+                            encoder,
+                            client,
+                            errors,
+                            spooled: 0,
+                        }
+                        .run(&shared)
+                    })?,
+            );
+        }
+
+        let mut pending = self.shared.pending.lock();
+        pending.bytes += E::entry_len(&entry);
+        pending.entries.push(entry);
+        pending.pushed += 1;
+        pending.since.get_or_insert_with(Instant::now);
+
+        // while the background thread is held up, e.g. backing off, discard the oldest
+        let mut discard = 0;
+        let mut bytes = pending.bytes;
+        while bytes > self.settings.max_buffer && discard + 1 < pending.entries.len() {
+            bytes -= E::entry_len(&pending.entries[discard]);
+            discard += 1;
+        }
+        if discard > 0 {
+            pending.entries.drain(..discard);
+            pending.bytes = bytes;
+            pending.taken += discard as u64;
+            pending.done += discard as u64;
+            pending.discarded += discard as u64;
+        }
+
+        self.shared.wake.notify_one();
+        Ok(())
+    }
+
+    /// Send every entry queued so far, whether its batch is full or not, and wait until
+    /// they have been sent or spooled.
+    pub(crate) fn flush(&self) {
+        let sender = match &self.sender {
+            Some(sender) => sender,
+            None => return,
+        };
+
+        let mut pending = self.shared.pending.lock();
+        let until = pending.pushed;
+        pending.flush_until = pending.flush_until.max(until);
+        self.shared.wake.notify_one();
+        while pending.done < until && !sender.is_finished() {
+            self.shared
+                .done
+                .wait_for(&mut pending, Duration::from_millis(100));
+        }
+    }
+}
+
+impl<E: Encoder> Drop for Batcher<E> {
+    fn drop(&mut self) {
+        self.shared.pending.lock().closed = true;
+        self.shared.wake.notify_one();
+        if let Some(sender) = self.sender.take() {
+            let _ = sender.join();
+        }
+    }
+}
+
+pub struct HttpOutput {
+    batcher: Batcher<HttpBatchFormat>,
+}
+
+impl Default for HttpOutput {
+    fn default() -> Self {
+        Self::new(DEFAULT_URL)
+    }
+}
+
+impl HttpOutput {
+    /// Send batches to `url`, e.g. `https://logs.example.com/ingest`.
+    pub fn new<S: Into<String>>(url: S) -> Self {
+        Self {
+            batcher: Batcher::new(Settings::new(url.into()), HttpBatchFormat::default()),
+        }
+    }
+
+    /// The settings of this output, for outputs built on it.
+    #[cfg(feature = "loki")]
+    pub(crate) fn settings(&self) -> &Settings {
+        &self.batcher.settings
+    }
+
+    fn settings_mut(&mut self) -> &mut Settings {
+        &mut self.batcher.settings
+    }
+
+    /// Set how entries are combined into a request body, newline delimited by default.
+    pub fn set_format(&mut self, format: HttpBatchFormat) {
+        if let Some(encoder) = self.batcher.encoder_mut() {
+            *encoder = format;
+        }
+    }
+
+    /// Set how entries are combined into a request body, newline delimited by default.
+    /// Useful for chaining operations.
+    #[must_use]
+    pub fn with_format(mut self, format: HttpBatchFormat) -> Self {
+        self.set_format(format);
+        self
+    }
+
+    /// Add a header to every request, e.g. for authorization. A `Content-Type` header
+    /// replaces the one matching the format.
+    pub fn set_header<N: Into<String>, V: Into<String>>(&mut self, name: N, value: V) {
+        self.settings_mut()
+            .headers
+            .push((name.into(), value.into()));
+    }
+
+    /// Add a header to every request, e.g. for authorization. Useful for chaining operations.
+    #[must_use]
+    pub fn with_header<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.set_header(name, value);
+        self
+    }
+
+    /// Set the most entries sent in one request, 100 by default.
+    pub fn set_max_batch_entries(&mut self, max: usize) {
+        self.settings_mut().max_batch_entries = max.max(1);
+    }
+
+    /// Set the most entries sent in one request, 100 by default. Useful for chaining
+    /// operations.
+    #[must_use]
+    pub fn with_max_batch_entries(mut self, max: usize) -> Self {
+        self.set_max_batch_entries(max);
+        self
+    }
+
+    /// Set the most bytes of entries sent in one request, 1MiB by default. Larger entries
+    /// are sent on their own.
+    pub fn set_max_batch_bytes(&mut self, max: usize) {
+        self.settings_mut().max_batch_bytes = max;
+    }
+
+    /// Set the most bytes of entries sent in one request, 1MiB by default. Useful for
+    /// chaining operations.
+    #[must_use]
+    pub fn with_max_batch_bytes(mut self, max: usize) -> Self {
+        self.set_max_batch_bytes(max);
+        self
+    }
+
+    /// Set how long an entry may wait for its batch to fill up, 1 second by default.
+    pub fn set_max_batch_age(&mut self, max: Duration) {
+        self.settings_mut().max_batch_age = max;
+    }
+
+    /// Set how long an entry may wait for its batch to fill up, 1 second by default. Useful
+    /// for chaining operations.
+    #[must_use]
+    pub fn with_max_batch_age(mut self, max: Duration) -> Self {
+        self.set_max_batch_age(max);
+        self
+    }
+
+    /// Set how often a batch is retried after a server error or connection failure, 3 by
+    /// default.
+    pub fn set_retries(&mut self, retries: u32) {
+        self.settings_mut().retries = retries;
+    }
+
+    /// Set how often a batch is retried after a server error or connection failure, 3 by
+    /// default. Useful for chaining operations.
+    #[must_use]
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.set_retries(retries);
+        self
+    }
+
+    /// Set the delay before the first retry, doubled after every failed attempt up to `max`.
+    /// 100 milliseconds and 10 seconds by default.
+    pub fn set_backoff(&mut self, min: Duration, max: Duration) {
+        let settings = self.settings_mut();
+        settings.min_backoff = min;
+        settings.max_backoff = max.max(min);
+    }
+
+    /// Set the delay before the first retry, doubled after every failed attempt up to `max`.
+    /// 100 milliseconds and 10 seconds by default. Useful for chaining operations.
+    #[must_use]
+    pub fn with_backoff(mut self, min: Duration, max: Duration) -> Self {
+        self.set_backoff(min, max);
+        self
+    }
+
+    /// Set how long a request may take before giving up, 10 seconds by default.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.settings_mut().timeout = timeout;
+    }
+
+    /// Set how long a request may take before giving up, 10 seconds by default. Useful for
+    /// chaining operations.
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.set_timeout(timeout);
+        self
+    }
+
+    /// Keep batches that could not be delivered in `dir`, to be sent once the endpoint is
+    /// back. Batches left from an earlier run are sent as well.
+    pub fn set_spool<P: Into<PathBuf>>(&mut self, dir: P) {
+        self.settings_mut().spool = Some(dir.into());
+    }
+
+    /// Keep batches that could not be delivered in `dir`. Useful for chaining operations.
+    #[must_use]
+    pub fn with_spool<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.set_spool(dir);
+        self
+    }
+
+    /// Set how many bytes of batches are kept in the spool directory, 64MiB by default.
+    /// Once full the oldest batches are discarded.
+    pub fn set_max_spool(&mut self, max: u64) {
+        self.settings_mut().max_spool = max;
+    }
+
+    /// Set how many bytes of batches are kept in the spool directory, 64MiB by default.
+    /// Useful for chaining operations.
+    #[must_use]
+    pub fn with_max_spool(mut self, max: u64) -> Self {
+        self.set_max_spool(max);
+        self
+    }
+
+    /// Set how many bytes of entries may wait to be sent, 8MiB by default. Once full, e.g.
+    /// while retrying, the oldest entries are discarded.
+    pub fn set_max_buffer(&mut self, max: usize) {
+        self.settings_mut().max_buffer = max;
+    }
+
+    /// Set how many bytes of entries may wait to be sent, 8MiB by default. Useful for
+    /// chaining operations.
+    #[must_use]
+    pub fn with_max_buffer(mut self, max: usize) -> Self {
+        self.set_max_buffer(max);
+        self
+    }
+}
+
+impl TacitOutput for HttpOutput {
+    fn set_error_reporter(&mut self, reporter: ErrorReporter) {
+        self.batcher.set_error_reporter(reporter);
+    }
+
+    fn ignores(metadata: &Metadata) -> bool {
+        is_sender_record(metadata)
+    }
+
+    /// Send the pending batch without waiting for it to fill up, and wait until it has
+    /// been sent or spooled.
+    fn force_flush(&mut self) -> io::Result<()> {
+        self.batcher.flush();
+        Ok(())
+    }
+}
+
+impl Write for HttpOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let entry = buf.strip_suffix(b"\n").unwrap_or(buf);
+        self.batcher.push(entry.to_vec())?;
+        Ok(buf.len())
+    }
+
+    /// Batches are sent once they fill up or are old enough, `force_flush` sends the
+    /// pending one right away.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Why a request failed, and whether it is worth trying again.
+struct Failure {
+    reason: String,
+    retry: bool,
+}
+
+/// Body of the background thread, owning the HTTP client and the spool.
+struct Sender<E: Encoder> {
+    settings: Settings,
+    encoder: E,
+    client: Client,
+    errors: ErrorReporter,
+    spooled: u64,
+}
+
+impl<E: Encoder> Sender<E> {
+    fn run(&mut self, shared: &Shared<E::Entry>) {
+        loop {
+            let batches = {
+                let mut pending = shared.pending.lock();
+                let send_all = loop {
+                    if pending.closed || pending.flush_until > pending.taken {
+                        break true;
+                    }
+                    if pending.entries.len() >= self.settings.max_batch_entries
+                        || pending.bytes >= self.settings.max_batch_bytes
+                    {
+                        break false;
+                    }
+                    match pending.since {
+                        Some(since) if since.elapsed() >= self.settings.max_batch_age => {
+                            break true
+                        }
+                        Some(since) => {
+                            let remaining = self.settings.max_batch_age - since.elapsed();
+                            shared.wake.wait_for(&mut pending, remaining);
+                        }
+                        None => shared.wake.wait(&mut pending),
+                    }
+                };
+                if pending.entries.is_empty() {
+                    return;
+                }
+                if pending.discarded > 0 {
+                    let err = io::Error::other(format!(
+                        "discarded {} entries while unable to keep up with {}",
+                        pending.discarded, self.settings.url
+                    ));
+                    self.errors.report(&err, None);
+                    pending.discarded = 0;
+                }
+
+                // full batches are sent right away, the rest waits unless it is old enough
+                let mut batches = Vec::new();
+                loop {
+                    let len = self.batch_len(&pending.entries);
+                    let bytes: usize = pending.entries[..len].iter().map(E::entry_len).sum();
+                    let full = len < pending.entries.len()
+                        || len >= self.settings.max_batch_entries
+                        || bytes >= self.settings.max_batch_bytes;
+                    if len == 0 || !(full || send_all) {
+                        break;
+                    }
+                    batches.push(pending.entries.drain(..len).collect::<Vec<_>>());
+                    pending.bytes -= bytes;
+                    pending.taken += len as u64;
+                }
+                if pending.entries.is_empty() {
+                    pending.since = None;
+                }
+                batches
+            };
+
+            for batch in batches {
+                if let Err(err) = self
+                    .encoder
+                    .encode(&batch)
+                    .and_then(|body| self.deliver(body))
+                {
+                    let mut lost = Vec::new();
+                    for entry in &batch {
+                        lost.extend_from_slice(E::entry_line(entry));
+                        lost.push(b'\n');
+                    }
+                    self.errors.report(&err, Some(&lost));
+                }
+                shared.pending.lock().done += batch.len() as u64;
+                shared.done.notify_all();
+            }
+        }
+    }
+
+    /// Number of entries at the start of `entries` that fit in one batch.
+    fn batch_len(&self, entries: &[E::Entry]) -> usize {
+        let mut bytes = 0;
+        for (index, entry) in entries.iter().enumerate() {
+            let len = E::entry_len(entry);
+            if index > 0
+                && (index >= self.settings.max_batch_entries
+                    || bytes + len > self.settings.max_batch_bytes)
+            {
+                return index;
+            }
+            bytes += len;
+        }
+        entries.len()
+    }
+
+    /// Send a batch, retrying and spooling it as configured. Fails once it is lost.
+    fn deliver(&mut self, body: Vec<u8>) -> io::Result<()> {
+        if let Some(spool) = self.settings.spool.clone() {
+            // keep batches in order, while older ones can not be sent neither can this one
+            if !self.resend_spooled(&spool) {
+                return self.spool(&spool, &body);
+            }
+        }
+
+        let mut backoff = self.settings.min_backoff;
+        let mut attempt = 0;
+        let failure = loop {
+            match self.send(body.clone()) {
+                Ok(()) => return Ok(()),
+                Err(failure) if failure.retry && attempt < self.settings.retries => {
+                    attempt += 1;
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(self.settings.max_backoff);
+                }
+                Err(failure) => break failure,
+            }
+        };
+
+        match &self.settings.spool {
+            Some(spool) if failure.retry => self.spool(&spool.clone(), &body),
+            _ => Err(io::Error::other(format!(
+                "unable to send {} bytes to {}: {}",
+                body.len(),
+                self.settings.url,
+                failure.reason
+            ))),
+        }
+    }
+
+    fn send(&self, body: Vec<u8>) -> Result<(), Failure> {
+        let mut request = self.client.post(&self.settings.url).body(body);
+        if !self
+            .settings
+            .headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+        {
+            request = request.header("Content-Type", self.encoder.content_type());
+        }
+        for (name, value) in &self.settings.headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+
+        match request.send() {
+            Ok(response) if response.status().is_success() => Ok(()),
+            Ok(response) => Err(Failure {
+                reason: format!("server responded with {}", response.status()),
+                retry: response.status().is_server_error(),
+            }),
+            Err(err) => Err(Failure {
+                retry: !err.is_builder(),
+                reason: err.to_string(),
+            }),
+        }
+    }
+
+    /// Send spooled batches once each, oldest first. Returns whether the spool was emptied.
+    fn resend_spooled(&self, dir: &Path) -> bool {
+        for (path, _) in spooled(dir) {
+            let body = match fs::read(&path) {
+                Ok(body) => body,
+                Err(_) => continue,
+            };
+            match self.send(body) {
+                Ok(()) => {}
+                Err(failure) if failure.retry => return false,
+                Err(failure) => {
+                    let err = io::Error::other(format!(
+                        "discarding spooled batch {}: {}",
+                        path.display(),
+                        failure.reason
+                    ));
+                    self.errors.report(&err, None);
+                }
+            }
+            let _ = fs::remove_file(&path);
+        }
+        true
+    }
+
+    /// Keep a batch in the spool directory, discarding the oldest ones once it is full.
+    fn spool(&mut self, dir: &Path, body: &[u8]) -> io::Result<()> {
+        self.spooled += 1;
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis())
+            .unwrap_or_default();
+        let path = dir.join(format!(
+            "{:013}-{:010}.{}",
+            millis, self.spooled, SPOOL_EXTENSION
+        ));
+        let partial = path.with_extension("partial");
+
+        let result = fs::create_dir_all(dir)
+            .and_then(|_| fs::write(&partial, body))
+            .and_then(|_| fs::rename(&partial, &path));
+        if let Err(err) = result {
+            let _ = fs::remove_file(&partial);
+            return Err(io::Error::new(
+                err.kind(),
+                format!("unable to spool batch to {}: {}", path.display(), err),
+            ));
+        }
+
+        let batches = spooled(dir);
+        let mut total: u64 = batches.iter().map(|(_, len)| len).sum();
+        for (path, len) in batches {
+            if total <= self.settings.max_spool {
+                break;
+            }
+            if fs::remove_file(&path).is_ok() {
+                total -= len;
+                let err = io::Error::other(format!(
+                    "discarded spooled batch {} while the spool was full",
+                    path.display()
+                ));
+                self.errors.report(&err, None);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Spooled batches and their sizes, oldest first.
+fn spooled(dir: &Path) -> Vec<(PathBuf, u64)> {
+    let mut batches: Vec<_> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| {
+            entry
+                .path()
+                .extension()
+                .is_some_and(|ext| ext == SPOOL_EXTENSION)
+        })
+        .map(|entry| {
+            let len = entry.metadata().map(|meta| meta.len()).unwrap_or_default();
+            (entry.path(), len)
+        })
+        .collect();
+    batches.sort();
+    batches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{serve, Request};
+    use std::sync::mpsc::Receiver;
+
+    /// Bodies of the next `count` requests, making sure no others follow.
+    fn bodies(requests: &Receiver<Request>, count: usize) -> Vec<String> {
+        let bodies = (0..count)
+            .map(|_| {
+                requests
+                    .recv_timeout(Duration::from_secs(5))
+                    .unwrap()
+                    .text()
+            })
+            .collect();
+        assert!(requests.recv_timeout(Duration::from_millis(100)).is_err());
+        bodies
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tacit-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn batches_entries_by_count() {
+        let (url, requests) = serve(Vec::new());
+        let mut output = HttpOutput::new(format!("{}/ingest", url))
+            .with_max_batch_entries(2)
+            .with_max_batch_age(Duration::from_secs(60))
+            .with_header("Authorization", "Bearer secret");
+
+        output.write_all(b"{\"n\":1}\n").unwrap();
+        output.write_all(b"{\"n\":2}\n").unwrap();
+        output.write_all(b"{\"n\":3}\n").unwrap();
+
+        let first = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(first.path, "/ingest");
+        assert_eq!(first.text(), "{\"n\":1}\n{\"n\":2}\n");
+        assert_eq!(first.header("content-type"), Some("application/x-ndjson"));
+        assert_eq!(first.header("authorization"), Some("Bearer secret"));
+
+        // the last entry waits for its batch to fill up, or the output to be dropped
+        assert!(requests.recv_timeout(Duration::from_millis(200)).is_err());
+        drop(output);
+        assert_eq!(bodies(&requests, 1), vec!["{\"n\":3}\n"]);
+    }
+
+    #[test]
+    fn sends_old_batches_as_json_arrays() {
+        let (url, requests) = serve(Vec::new());
+        let mut output = HttpOutput::new(url)
+            .with_format(HttpBatchFormat::JsonArray)
+            .with_max_batch_age(Duration::from_millis(50));
+
+        output.write_all(b"{\"n\":1}\n").unwrap();
+        output.write_all(b"{\"n\":2}\n").unwrap();
+
+        let request = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(request.text(), "[{\"n\":1},{\"n\":2}]");
+        assert_eq!(request.header("content-type"), Some("application/json"));
+    }
+
+    #[test]
+    fn retries_server_errors() {
+        let (url, requests) = serve(vec![503, 500, 400]);
+        let mut output = HttpOutput::new(url)
+            .with_max_batch_entries(1)
+            .with_backoff(Duration::from_millis(1), Duration::from_millis(10));
+
+        output.write_all(b"{\"n\":1}\n").unwrap();
+        output.write_all(b"{\"n\":2}\n").unwrap();
+        drop(output);
+
+        // the client error is final, only server errors are retried
+        assert_eq!(
+            bodies(&requests, 4),
+            vec!["{\"n\":1}\n", "{\"n\":1}\n", "{\"n\":1}\n", "{\"n\":2}\n"]
+        );
+    }
+
+    #[test]
+    fn spools_batches_while_the_endpoint_is_down() {
+        let dir = temp_dir("http-spool");
+        let (url, requests) = serve(vec![500, 200, 200]);
+        let mut output = HttpOutput::new(url)
+            .with_max_batch_entries(1)
+            .with_retries(0)
+            .with_spool(&dir);
+
+        output.write_all(b"{\"n\":1}\n").unwrap();
+        let failed = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(failed.text(), "{\"n\":1}\n");
+        output.write_all(b"{\"n\":2}\n").unwrap();
+        drop(output);
+
+        assert_eq!(bodies(&requests, 2), vec!["{\"n\":1}\n", "{\"n\":2}\n"]);
+        assert!(spooled(&dir).is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn force_flush_sends_the_pending_batch() {
+        let (url, requests) = serve(Vec::new());
+        let mut output = HttpOutput::new(url).with_max_batch_age(Duration::from_secs(60));
+
+        output.write_all(b"{\"n\":1}\n").unwrap();
+        output.force_flush().unwrap();
+
+        // sent before flush returned, not when the output is dropped
+        assert_eq!(
+            requests.try_recv().map(|request| request.text()).ok(),
+            Some(String::from("{\"n\":1}\n"))
+        );
+        drop(output);
+        assert_eq!(bodies(&requests, 0), Vec::<String>::new());
+    }
+
+    #[test]
+    fn discards_oldest_entries_once_the_buffer_is_full() {
+        let (url, requests) = serve(vec![503]);
+        let mut output = HttpOutput::new(url)
+            .with_max_batch_entries(1)
+            .with_retries(1)
+            .with_backoff(Duration::from_millis(300), Duration::from_millis(300))
+            .with_max_buffer(14);
+
+        output.write_all(b"{\"n\":1}\n").unwrap();
+        // the first entry is being retried while the rest comes in
+        let failed = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(failed.text(), "{\"n\":1}\n");
+        for n in 2..=5 {
+            output
+                .write_all(format!("{{\"n\":{}}}\n", n).as_bytes())
+                .unwrap();
+        }
+        output.force_flush().unwrap();
+
+        assert_eq!(
+            bodies(&requests, 3),
+            vec!["{\"n\":1}\n", "{\"n\":4}\n", "{\"n\":5}\n"]
+        );
+    }
+
+    #[test]
+    fn leaves_out_records_from_sending_batches() {
+        use crate::{Logger, SimpleFormatter};
+        use log::{Log, Record};
+
+        let (url, requests) = serve(Vec::new());
+        let logger = Logger::new(
+            HttpOutput::new(url).with_max_batch_age(Duration::from_secs(60)),
+            SimpleFormatter::default(),
+        )
+        .with_level_filter(log::LevelFilter::Trace);
+        let log = |target: &str, message: &str| {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(log::Level::Trace)
+                    .target(target)
+                    .build(),
+            )
+        };
+
+        log("reqwest::blocking::wait", "client");
+        log("hyper", "connection");
+        log("hyperion", "kept");
+        thread::scope(|scope| {
+            thread::Builder::new()
+                .name(String::from(SENDER_THREAD))
+                .spawn_scoped(scope, || log("my_app", "sender"))
+                .unwrap()
+                .join()
+                .unwrap()
+        });
+        log("my_app", "kept");
+        logger.flush();
+
+        assert_eq!(bodies(&requests, 1), vec!["msg=kept\nmsg=kept\n"]);
+    }
+
+    #[cfg(feature = "threaded")]
+    #[test]
+    fn async_writes_leave_batches_to_fill_up() {
+        use crate::{Logger, SimpleFormatter};
+        use log::{Log, Record};
+
+        let (url, requests) = serve(Vec::new());
+        let logger = Logger::new(
+            HttpOutput::new(url).with_max_batch_age(Duration::from_secs(60)),
+            SimpleFormatter::default(),
+        )
+        .with_async_writes(4)
+        .finalize();
+
+        for n in 0..3 {
+            logger.log(&Record::builder().args(format_args!("{}", n)).build());
+            // the writer drains and flushes its queue after every entry
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert!(requests.recv_timeout(Duration::from_millis(100)).is_err());
+        logger.flush();
+
+        assert_eq!(bodies(&requests, 1), vec!["msg=0\nmsg=1\nmsg=2\n"]);
+    }
+
+    #[test]
+    fn hands_lost_batches_to_the_error_policy() {
+        use crate::{test_util::SharedOutput, ErrorPolicy, Logger, SimpleFormatter};
+        use log::{Log, Record};
+
+        let (url, _requests) = serve(vec![400]);
+        let fallback = SharedOutput::default();
+        let logger = Logger::new(HttpOutput::new(url), SimpleFormatter::default())
+            .with_error_policy(ErrorPolicy::fallback(fallback.clone()))
+            .finalize();
+
+        logger.log(&Record::builder().args(format_args!("lost")).build());
+        logger.flush();
+
+        assert_eq!(fallback.contents(), "msg=lost\n");
+    }
+}
@@ -0,0 +1,465 @@
+//! # Journald Output
+//! Send entries to systemd-journald over its native protocol, preserving
+//! structure as journal fields.
+//!
+//! ```sh
+//! MESSAGE=level=INFO msg="logging a thing"
+//! PRIORITY=6
+//! CODE_FILE=src/main.rs
+//! CODE_LINE=12
+//! CODE_MODULE=my_app
+//! SYSLOG_IDENTIFIER=my_app
+//! LEVEL=INFO
+//! ```
+//!
+//! The rendered entry, without its trailing newline, becomes `MESSAGE`, use a
+//! `PatternFormatter` with `{message}` to send the bare message. Every property
+//! and key value pair is added as a field, with its name uppercased and any
+//! characters journald does not allow replaced with `_`. Names clashing with
+//! journald's own fields, such as `message` or `code_file`, are prefixed with
+//! `PROP_` so they can not replace them. Entries too large for a single
+//! datagram are passed to journald through a sealed memfd.
+
+use super::{syslog_severity, RecordInfo, TacitOutput};
+use crate::StaticProperty;
+use std::{
+    fs::File,
+    io::{self, Write},
+    mem,
+    os::unix::{
+        io::{AsRawFd, FromRawFd},
+        net::UnixDatagram,
+    },
+    path::PathBuf,
+    ptr,
+};
+
+/// Path of journald's native protocol socket.
+const DEFAULT_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Longest field name journald accepts.
+const MAX_FIELD_NAME: usize = 64;
+
+/// Fields written by this output or with a meaning to journald, which properties must not
+/// replace.
+const RESERVED_FIELDS: &[&str] = &[
+    "MESSAGE",
+    "MESSAGE_ID",
+    "PRIORITY",
+    "CODE_FILE",
+    "CODE_LINE",
+    "CODE_FUNC",
+    "CODE_MODULE",
+    "ERRNO",
+    "TID",
+    "SYSLOG_IDENTIFIER",
+    "SYSLOG_FACILITY",
+    "SYSLOG_PID",
+    "SYSLOG_TIMESTAMP",
+    "SYSLOG_RAW",
+];
+
+/// Prefix of property names clashing with reserved fields.
+const RESERVED_PREFIX: &str = "PROP_";
+
+pub struct JournaldOutput {
+    path: PathBuf,
+    syslog_identifier: String,
+    socket: Option<UnixDatagram>,
+}
+
+impl Default for JournaldOutput {
+    fn default() -> Self {
+        Self::new(DEFAULT_SOCKET)
+    }
+}
+
+impl JournaldOutput {
+    /// Send entries to the journal socket at `path`.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        let syslog_identifier = std::env::current_exe()
+            .ok()
+            .and_then(|exe| {
+                exe.file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+            })
+            .unwrap_or_else(|| String::from("tacit"));
+
+        Self {
+            path: path.into(),
+            syslog_identifier,
+            socket: None,
+        }
+    }
+
+    /// Set the `SYSLOG_IDENTIFIER` field, the executable's name by default.
+    pub fn set_syslog_identifier<S: Into<String>>(&mut self, syslog_identifier: S) {
+        self.syslog_identifier = syslog_identifier.into();
+    }
+
+    /// Set the `SYSLOG_IDENTIFIER` field, the executable's name by default. Useful for
+    /// chaining operations.
+    #[must_use]
+    pub fn with_syslog_identifier<S: Into<String>>(mut self, syslog_identifier: S) -> Self {
+        self.set_syslog_identifier(syslog_identifier);
+        self
+    }
+
+    /// Serialize an entry's fields in journald's native format.
+    fn message(&self, info: &RecordInfo, entry: &[u8]) -> Vec<u8> {
+        let mut message = Vec::with_capacity(entry.len() + 256);
+        let msg = entry
+            .strip_suffix(b"\n")
+            .map(|msg| msg.strip_suffix(b"\r").unwrap_or(msg))
+            .unwrap_or(entry);
+
+        push_field(&mut message, "MESSAGE", msg);
+        push_field(
+            &mut message,
+            "PRIORITY",
+            syslog_severity(info.level()).to_string().as_bytes(),
+        );
+        if let Some(file) = info.file() {
+            push_field(&mut message, "CODE_FILE", file.as_bytes());
+        }
+        if let Some(line) = info.line() {
+            push_field(&mut message, "CODE_LINE", line.to_string().as_bytes());
+        }
+        if let Some(module) = info.module_path() {
+            push_field(&mut message, "CODE_MODULE", module.as_bytes());
+        }
+        push_field(
+            &mut message,
+            "SYSLOG_IDENTIFIER",
+            self.syslog_identifier.as_bytes(),
+        );
+
+        for (name, value) in info.fields() {
+            let name = field_name(name);
+            if name.is_empty() {
+                continue;
+            }
+
+            let value = match value {
+                StaticProperty::String(v) => v.to_string(),
+                StaticProperty::Number(v) => v.to_string(),
+                StaticProperty::Null => String::new(),
+            };
+            push_field(&mut message, &name, value.as_bytes());
+        }
+
+        message
+    }
+
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        if self.socket.is_none() {
+            let socket = UnixDatagram::unbound()?;
+            socket.connect(&self.path)?;
+            self.socket = Some(socket);
+        }
+        let socket = match &self.socket {
+            Some(socket) => socket,
+            None => return Ok(()),
+        };
+
+        let result = match socket.send(message) {
+            Err(err)
+                if err.raw_os_error() == Some(libc::EMSGSIZE)
+                    || err.raw_os_error() == Some(libc::ENOBUFS) =>
+            {
+                send_memfd(socket, message)
+            }
+            result => result.map(|_| ()),
+        };
+
+        if result.is_err() {
+            // reconnect on the next entry
+            self.socket = None;
+        }
+        result
+    }
+}
+
+/// Append a field, using the binary form when the value contains a newline.
+fn push_field(message: &mut Vec<u8>, name: &str, value: &[u8]) {
+    message.extend_from_slice(name.as_bytes());
+    if value.contains(&b'\n') {
+        message.push(b'\n');
+        message.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        message.push(b'=');
+    }
+    message.extend_from_slice(value);
+    message.push(b'\n');
+}
+
+/// Journal field name for a property, uppercase letters, digits and underscores, not
+/// starting with an underscore or digit, and prefixed if it clashes with a reserved field.
+fn field_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c.to_ascii_uppercase() {
+            c @ ('A'..='Z' | '0'..='9') => c,
+            _ => '_',
+        })
+        .skip_while(|c| *c == '_' || c.is_ascii_digit())
+        .take(MAX_FIELD_NAME)
+        .collect();
+
+    if RESERVED_FIELDS.contains(&name.as_str()) {
+        format!("{}{}", RESERVED_PREFIX, name)
+    } else {
+        name
+    }
+}
+
+/// Pass an entry too large for a datagram through a sealed memfd, as journald expects.
+fn send_memfd(socket: &UnixDatagram, message: &[u8]) -> io::Result<()> {
+    // SAFETY: the name is a valid nul terminated string, and the returned descriptor is
+    // owned by the file from here on.
+    let mut file = unsafe {
+        let fd = libc::memfd_create(
+            b"tacit-journal\0".as_ptr().cast(),
+            libc::MFD_ALLOW_SEALING | libc::MFD_CLOEXEC,
+        );
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        File::from_raw_fd(fd)
+    };
+    file.write_all(message)?;
+
+    let fd = file.as_raw_fd();
+    let seals = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE | libc::F_SEAL_SEAL;
+    // SAFETY: `fd` is an open memfd created with sealing allowed.
+    if unsafe { libc::fcntl(fd, libc::F_ADD_SEALS, seals) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: the control buffer is sized with CMSG_SPACE for a single descriptor, so the
+    // first header and its data fit within it, and `msg` only points at live buffers.
+    unsafe {
+        let mut control =
+            vec![0u8; libc::CMSG_SPACE(mem::size_of::<libc::c_int>() as u32) as usize];
+        let mut msg: libc::msghdr = mem::zeroed();
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = control.len() as _;
+
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<libc::c_int>() as u32) as _;
+        ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast::<libc::c_int>(), fd);
+
+        if libc::sendmsg(socket.as_raw_fd(), &msg, libc::MSG_NOSIGNAL) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+impl TacitOutput for JournaldOutput {
+    const WANTS_FIELDS: bool = true;
+
+    fn write_entry(&mut self, info: &RecordInfo, entry: &[u8]) -> io::Result<()> {
+        let message = self.message(info, entry);
+        self.send(&message)
+    }
+}
+
+impl Write for JournaldOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let info = RecordInfo::from(&log::Record::builder().level(log::Level::Info).build());
+        self.write_entry(&info, buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Logger, PatternFormatter};
+    use log::{Level, Log, Record};
+    use std::{
+        io::{Read, Seek, SeekFrom},
+        os::unix::io::RawFd,
+    };
+
+    struct Journal {
+        path: PathBuf,
+        socket: UnixDatagram,
+    }
+
+    impl Journal {
+        fn bind(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("tacit-{}-{}", std::process::id(), name));
+            let _ = std::fs::remove_file(&path);
+            let socket = UnixDatagram::bind(&path).unwrap();
+            Self { path, socket }
+        }
+
+        /// Receive a datagram, reading it from a passed memfd when there is one.
+        fn recv(&self) -> Vec<u8> {
+            let mut data = vec![0u8; 64 * 1024];
+            let mut control = vec![0u8; 64];
+            // SAFETY: `msg` points at live buffers of the given lengths, and any passed
+            // descriptor is owned by a file once received.
+            unsafe {
+                let mut iov = libc::iovec {
+                    iov_base: data.as_mut_ptr().cast(),
+                    iov_len: data.len(),
+                };
+                let mut msg: libc::msghdr = mem::zeroed();
+                msg.msg_iov = &mut iov;
+                msg.msg_iovlen = 1;
+                msg.msg_control = control.as_mut_ptr().cast();
+                msg.msg_controllen = control.len() as _;
+
+                let len = libc::recvmsg(self.socket.as_raw_fd(), &mut msg, 0);
+                assert!(len >= 0);
+                data.truncate(len as usize);
+
+                let cmsg = libc::CMSG_FIRSTHDR(&msg);
+                if !cmsg.is_null() && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                    let fd: RawFd = ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast());
+                    let mut file = File::from_raw_fd(fd);
+                    data.clear();
+                    // the descriptor shares the sender's offset, at the end of the entry
+                    file.seek(SeekFrom::Start(0)).unwrap();
+                    file.read_to_end(&mut data).unwrap();
+                }
+            }
+            data
+        }
+    }
+
+    impl Drop for Journal {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    /// Parse journald's native format into its fields.
+    fn parse(mut data: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut fields = Vec::new();
+        while !data.is_empty() {
+            let end = data.iter().position(|b| *b == b'\n').unwrap();
+            let line = &data[..end];
+            match line.iter().position(|b| *b == b'=') {
+                Some(eq) => {
+                    fields.push((
+                        String::from_utf8(line[..eq].to_vec()).unwrap(),
+                        line[eq + 1..].to_vec(),
+                    ));
+                    data = &data[end + 1..];
+                }
+                None => {
+                    let rest = &data[end + 1..];
+                    let mut len = [0u8; 8];
+                    len.copy_from_slice(&rest[..8]);
+                    let len = u64::from_le_bytes(len) as usize;
+                    fields.push((
+                        String::from_utf8(line.to_vec()).unwrap(),
+                        rest[8..8 + len].to_vec(),
+                    ));
+                    data = &rest[8 + len + 1..];
+                }
+            }
+        }
+        fields
+    }
+
+    fn logger(journal: &Journal) -> Logger<JournaldOutput, PatternFormatter> {
+        Logger::new(
+            JournaldOutput::new(&journal.path).with_syslog_identifier("my_app"),
+            PatternFormatter::new("{message}").unwrap(),
+        )
+    }
+
+    #[test]
+    fn sends_native_fields() {
+        let journal = Journal::bind("journal");
+        let logger = logger(&journal)
+            .with_prop(String::from("request-id"), "a\nb".into())
+            .with_prop(String::from("_trusted"), 7i64.into());
+
+        logger.log(
+            &Record::builder()
+                .args(format_args!("disk full"))
+                .level(Level::Warn)
+                .module_path(Some("my_app::disk"))
+                .file(Some("src/disk.rs"))
+                .line(Some(12))
+                .build(),
+        );
+
+        let field = |name: &str, value: &[u8]| (String::from(name), value.to_vec());
+        assert_eq!(
+            parse(&journal.recv()),
+            vec![
+                field("MESSAGE", b"disk full"),
+                field("PRIORITY", b"4"),
+                field("CODE_FILE", b"src/disk.rs"),
+                field("CODE_LINE", b"12"),
+                field("CODE_MODULE", b"my_app::disk"),
+                field("SYSLOG_IDENTIFIER", b"my_app"),
+                field("REQUEST_ID", b"a\nb"),
+                field("TRUSTED", b"7"),
+            ]
+        );
+    }
+
+    #[test]
+    fn prefixes_reserved_property_names() {
+        let journal = Journal::bind("journal-reserved");
+        let logger = logger(&journal)
+            .with_prop(String::from("message"), "shadow".into())
+            .with_prop(String::from("Priority"), 0i64.into())
+            .with_prop(String::from("code-file"), "elsewhere.rs".into())
+            .with_prop(String::from("message_count"), 2i64.into());
+
+        logger.log(
+            &Record::builder()
+                .args(format_args!("disk full"))
+                .level(Level::Warn)
+                .build(),
+        );
+
+        let field = |name: &str, value: &[u8]| (String::from(name), value.to_vec());
+        assert_eq!(
+            parse(&journal.recv()),
+            vec![
+                field("MESSAGE", b"disk full"),
+                field("PRIORITY", b"4"),
+                field("SYSLOG_IDENTIFIER", b"my_app"),
+                field("PROP_MESSAGE", b"shadow"),
+                field("PROP_PRIORITY", b"0"),
+                field("PROP_CODE_FILE", b"elsewhere.rs"),
+                field("MESSAGE_COUNT", b"2"),
+            ]
+        );
+    }
+
+    #[test]
+    fn passes_large_entries_through_memfd() {
+        let journal = Journal::bind("journal-large");
+        let logger = logger(&journal);
+        let message = "x".repeat(8 * 1024 * 1024);
+
+        logger.log(
+            &Record::builder()
+                .args(format_args!("{}", message))
+                .level(Level::Error)
+                .build(),
+        );
+
+        let fields = parse(&journal.recv());
+        assert_eq!(fields[0].0, "MESSAGE");
+        assert_eq!(fields[0].1.len(), message.len());
+    }
+}
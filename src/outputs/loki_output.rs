@@ -0,0 +1,547 @@
+//! # Loki Output
+//! Push entries to Grafana Loki, grouped into streams by their labels.
+//!
+//! Only whitelisted properties are promoted to labels, `level` by default, so the
+//! number of streams stays bounded. A property that is not set falls back to the
+//! record's `level`, `target` or `module` of the same name. The `level` label is
+//! always lowercase. Entries without any label are pushed with `job="tacit"`.
+//!
+//! Batching, retries, spooling, flushing and error reporting work as for the
+//! `HttpOutput`, which can be
+//! configured first and turned into a `LokiOutput`, e.g. to add an `X-Scope-OrgID`
+//! tenant header.
+
+use super::{
+    http_output::{is_sender_record, Batcher, Encoder},
+    HttpOutput, RecordInfo, TacitOutput,
+};
+use crate::{ErrorReporter, StaticProperty};
+use log::Metadata;
+use serde_json::{json, Map, Value};
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Default Loki address used when no other is provided.
+const DEFAULT_URL: &str = "http://127.0.0.1:3100";
+const PUSH_PATH: &str = "/loki/api/v1/push";
+
+/// Label given to entries that would otherwise have none.
+const FALLBACK_LABEL: (&str, &str) = ("job", "tacit");
+
+/// Encoding of push requests.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LokiFormat {
+    /// Streams as JSON, sent as `application/json`.
+    #[default]
+    Json,
+    /// Streams as snappy compressed protobuf, sent as `application/x-protobuf`.
+    Protobuf,
+}
+
+pub(crate) struct LokiEntry {
+    labels: Vec<(String, String)>,
+    timestamp: u128,
+    line: String,
+}
+
+/// Entries grouped by their labels.
+fn streams(entries: &[LokiEntry]) -> BTreeMap<&[(String, String)], Vec<&LokiEntry>> {
+    let mut streams: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    for entry in entries {
+        streams.entry(&entry.labels[..]).or_default().push(entry);
+    }
+    streams
+}
+
+impl Encoder for LokiFormat {
+    type Entry = LokiEntry;
+
+    fn entry_len(entry: &LokiEntry) -> usize {
+        entry.line.len()
+    }
+
+    fn entry_line(entry: &LokiEntry) -> &[u8] {
+        entry.line.as_bytes()
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Protobuf => "application/x-protobuf",
+        }
+    }
+
+    fn encode(&self, entries: &[LokiEntry]) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Json => {
+                let streams: Vec<_> = streams(entries)
+                    .into_iter()
+                    .map(|(labels, entries)| {
+                        let stream: Map<_, _> = labels
+                            .iter()
+                            .map(|(name, value)| (name.clone(), Value::String(value.clone())))
+                            .collect();
+                        let values: Vec<_> = entries
+                            .iter()
+                            .map(|entry| json!([entry.timestamp.to_string(), entry.line]))
+                            .collect();
+                        json!({ "stream": stream, "values": values })
+                    })
+                    .collect();
+                Ok(serde_json::to_vec(&json!({ "streams": streams }))?)
+            }
+            Self::Protobuf => {
+                // PushRequest { repeated StreamAdapter streams = 1; }
+                let mut request = Vec::new();
+                for (labels, entries) in streams(entries) {
+                    // StreamAdapter { string labels = 1; repeated EntryAdapter entries = 2; }
+                    let mut stream = Vec::new();
+                    protobuf_bytes(&mut stream, 1, label_set(labels).as_bytes());
+                    for entry in entries {
+                        // Timestamp { int64 seconds = 1; int32 nanos = 2; }
+                        let mut timestamp = Vec::new();
+                        protobuf_varint(
+                            &mut timestamp,
+                            1,
+                            (entry.timestamp / 1_000_000_000) as u64,
+                        );
+                        protobuf_varint(
+                            &mut timestamp,
+                            2,
+                            (entry.timestamp % 1_000_000_000) as u64,
+                        );
+
+                        // EntryAdapter { Timestamp timestamp = 1; string line = 2; }
+                        let mut adapter = Vec::new();
+                        protobuf_bytes(&mut adapter, 1, &timestamp);
+                        protobuf_bytes(&mut adapter, 2, entry.line.as_bytes());
+                        protobuf_bytes(&mut stream, 2, &adapter);
+                    }
+                    protobuf_bytes(&mut request, 1, &stream);
+                }
+
+                snap::raw::Encoder::new()
+                    .compress_vec(&request)
+                    .map_err(io::Error::other)
+            }
+        }
+    }
+}
+
+/// Labels in Prometheus notation, e.g. `{level="info", service="api"}`.
+fn label_set(labels: &[(String, String)]) -> String {
+    let labels: Vec<_> = labels
+        .iter()
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect();
+    format!("{{{}}}", labels.join(", "))
+}
+
+fn varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn protobuf_varint(buf: &mut Vec<u8>, field: u64, value: u64) {
+    varint(buf, field << 3);
+    varint(buf, value);
+}
+
+fn protobuf_bytes(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    varint(buf, field << 3 | 2);
+    varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+/// Label names are letters, digits and underscores, not starting with a digit.
+fn label_name(name: &str) -> String {
+    let mut label: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if label.is_empty() || label.starts_with(|c: char| c.is_ascii_digit()) {
+        label.insert(0, '_');
+    }
+    label
+}
+
+pub struct LokiOutput {
+    batcher: Batcher<LokiFormat>,
+    labels: Vec<(String, String)>,
+    label_properties: Vec<String>,
+}
+
+impl Default for LokiOutput {
+    fn default() -> Self {
+        Self::new(DEFAULT_URL)
+    }
+}
+
+impl From<HttpOutput> for LokiOutput {
+    /// Push with the settings of an `HttpOutput`, whose URL is the full push endpoint.
+    fn from(output: HttpOutput) -> Self {
+        Self {
+            batcher: Batcher::new(output.settings().clone(), LokiFormat::default()),
+            labels: Vec::new(),
+            label_properties: vec![String::from("level")],
+        }
+    }
+}
+
+impl LokiOutput {
+    /// Push to the Loki instance at `url`, e.g. `http://loki:3100`.
+    pub fn new<S: Into<String>>(url: S) -> Self {
+        let url = url.into();
+        let url = if url.ends_with(PUSH_PATH) {
+            url
+        } else {
+            format!("{}{}", url.trim_end_matches('/'), PUSH_PATH)
+        };
+        Self::from(HttpOutput::new(url))
+    }
+
+    /// Set the encoding of push requests, JSON by default.
+    pub fn set_format(&mut self, format: LokiFormat) {
+        if let Some(encoder) = self.batcher.encoder_mut() {
+            *encoder = format;
+        }
+    }
+
+    /// Set the encoding of push requests, JSON by default. Useful for chaining operations.
+    #[must_use]
+    pub fn with_format(mut self, format: LokiFormat) -> Self {
+        self.set_format(format);
+        self
+    }
+
+    /// Add a label with a fixed value to every stream, e.g. `env`.
+    pub fn set_label<N: Into<String>, V: Into<String>>(&mut self, name: N, value: V) {
+        self.labels.push((label_name(&name.into()), value.into()));
+    }
+
+    /// Add a label with a fixed value to every stream. Useful for chaining operations.
+    #[must_use]
+    pub fn with_label<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.set_label(name, value);
+        self
+    }
+
+    /// Promote the property `name` to a label, in addition to `level`.
+    pub fn add_label_property<S: Into<String>>(&mut self, name: S) {
+        self.label_properties.push(name.into());
+    }
+
+    /// Promote the property `name` to a label, in addition to `level`. Useful for chaining
+    /// operations.
+    #[must_use]
+    pub fn with_label_property<S: Into<String>>(mut self, name: S) -> Self {
+        self.add_label_property(name);
+        self
+    }
+
+    /// Labels of the stream an entry belongs to.
+    fn stream_labels(&self, info: Option<&RecordInfo>) -> Vec<(String, String)> {
+        let mut labels: BTreeMap<String, String> = self.labels.iter().cloned().collect();
+
+        if let Some(info) = info {
+            for name in &self.label_properties {
+                let value = match info.field(name) {
+                    Some(StaticProperty::String(value)) => Some(value.clone()),
+                    Some(StaticProperty::Number(value)) => Some(value.to_string()),
+                    Some(StaticProperty::Null) => None,
+                    None => match name.as_str() {
+                        "level" => Some(info.level().to_string()),
+                        "target" => Some(info.target().to_string()),
+                        "module" => info.module_path().map(String::from),
+                        _ => None,
+                    },
+                };
+                // `INFO` from a property and the record's own level are the same stream
+                let value = match name.as_str() {
+                    "level" => value.map(|value| value.to_lowercase()),
+                    _ => value,
+                };
+                if let Some(value) = value.filter(|value| !value.is_empty()) {
+                    labels.insert(label_name(name), value);
+                }
+            }
+        }
+
+        if labels.is_empty() {
+            let (name, value) = FALLBACK_LABEL;
+            labels.insert(name.to_string(), value.to_string());
+        }
+        labels.into_iter().collect()
+    }
+
+    fn push(&mut self, info: Option<&RecordInfo>, entry: &[u8]) -> io::Result<()> {
+        let entry = entry.strip_suffix(b"\n").unwrap_or(entry);
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos())
+            .unwrap_or_default();
+        let entry = LokiEntry {
+            labels: self.stream_labels(info),
+            timestamp,
+            line: String::from_utf8_lossy(entry).into_owned(),
+        };
+        self.batcher.push(entry)
+    }
+}
+
+impl TacitOutput for LokiOutput {
+    const WANTS_FIELDS: bool = true;
+
+    fn write_entry(&mut self, info: &RecordInfo, entry: &[u8]) -> io::Result<()> {
+        self.push(Some(info), entry)
+    }
+
+    fn ignores(metadata: &Metadata) -> bool {
+        is_sender_record(metadata)
+    }
+
+    fn set_error_reporter(&mut self, reporter: ErrorReporter) {
+        self.batcher.set_error_reporter(reporter);
+    }
+
+    /// Push the pending batch without waiting for it to fill up, and wait until it has
+    /// been pushed or spooled.
+    fn force_flush(&mut self) -> io::Result<()> {
+        self.batcher.flush();
+        Ok(())
+    }
+}
+
+impl Write for LokiOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.push(None, buf)?;
+        Ok(buf.len())
+    }
+
+    /// Batches are pushed once they fill up or are old enough, `force_flush` pushes the
+    /// pending one right away.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util::serve, Property};
+    use log::{Level, Record};
+    use std::time::Duration;
+
+    fn record_info(level: Level, message: &str, props: &[(String, Property)]) -> RecordInfo {
+        let collect = |record: &Record| RecordInfo::from(record).with_fields(record, props, false);
+        collect(
+            &Record::builder()
+                .args(format_args!("{}", message))
+                .level(level)
+                .target("my_app")
+                .build(),
+        )
+    }
+
+    fn write_record(output: &mut LokiOutput, level: Level, message: &str, service: &str) {
+        let props = vec![
+            (String::from("service"), Property::Static(service.into())),
+            (String::from("request"), Property::Static("1".into())),
+        ];
+        output
+            .write_entry(
+                &record_info(level, message, &props),
+                format!("msg={}\n", message).as_bytes(),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn lowercases_level_labels() {
+        let output = LokiOutput::default();
+        let props = vec![(String::from("level"), Property::Static("INFO".into()))];
+
+        let expected = vec![(String::from("level"), String::from("info"))];
+        assert_eq!(
+            output.stream_labels(Some(&record_info(Level::Info, "one", &props))),
+            expected
+        );
+        assert_eq!(
+            output.stream_labels(Some(&record_info(Level::Info, "two", &[]))),
+            expected
+        );
+    }
+
+    #[test]
+    fn force_flush_pushes_the_pending_batch() {
+        let (url, requests) = serve(Vec::new());
+        let mut output = LokiOutput::new(url);
+
+        write_record(&mut output, Level::Info, "one", "api");
+        output.force_flush().unwrap();
+
+        // pushed before flush returned, not when the output is dropped
+        let request = requests.try_recv().unwrap();
+        assert_eq!(request.path, PUSH_PATH);
+        assert!(request.text().contains("msg=one"));
+    }
+
+    #[test]
+    fn logs_at_trace_without_pushing_its_own_records() {
+        use crate::{Logger, SimpleFormatter};
+
+        let (url, requests) = serve(Vec::new());
+        let output = LokiOutput::from(
+            HttpOutput::new(format!("{}{}", url, PUSH_PATH))
+                .with_max_batch_age(Duration::from_millis(50)),
+        );
+        let logger = Logger::new(output, SimpleFormatter::default())
+            .with_level_filter(log::LevelFilter::Trace);
+        // the only test registering a global logger, the HTTP client logs to it at trace
+        // level while pushing, which must neither deadlock nor be pushed in turn
+        let guard = crate::new().with_logger(logger).log().unwrap();
+        for message in &["one", "two"] {
+            log::trace!("{}", message);
+            log::logger().flush();
+        }
+        drop(guard);
+
+        for message in &["one", "two"] {
+            let body = requests
+                .recv_timeout(Duration::from_secs(5))
+                .unwrap()
+                .text();
+            assert_eq!(body.matches("msg=").count(), 1, "{}", body);
+            assert!(body.contains(&format!("msg={}", message)), "{}", body);
+        }
+        assert!(requests.recv_timeout(Duration::from_millis(500)).is_err());
+    }
+
+    #[test]
+    fn groups_entries_into_streams_by_label() {
+        let (url, requests) = serve(Vec::new());
+        let mut output = LokiOutput::new(url)
+            .with_label("env", "test")
+            .with_label_property("service");
+
+        write_record(&mut output, Level::Error, "one", "api");
+        write_record(&mut output, Level::Info, "two", "api");
+        write_record(&mut output, Level::Error, "three", "api");
+        write_record(&mut output, Level::Error, "four", "web");
+        drop(output);
+
+        let request = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(request.path, PUSH_PATH);
+        assert_eq!(request.header("content-type"), Some("application/json"));
+
+        let body: Value = serde_json::from_slice(&request.body).unwrap();
+        let streams: Vec<_> = body["streams"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|stream| {
+                let lines: Vec<_> = stream["values"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|value| value[1].as_str().unwrap().to_string())
+                    .collect();
+                (stream["stream"].clone(), lines)
+            })
+            .collect();
+        assert_eq!(
+            streams,
+            vec![
+                (
+                    json!({"env": "test", "level": "error", "service": "api"}),
+                    vec![String::from("msg=one"), String::from("msg=three")]
+                ),
+                (
+                    json!({"env": "test", "level": "error", "service": "web"}),
+                    vec![String::from("msg=four")]
+                ),
+                (
+                    json!({"env": "test", "level": "info", "service": "api"}),
+                    vec![String::from("msg=two")]
+                ),
+            ]
+        );
+    }
+
+    /// Length delimited fields of a protobuf message.
+    fn protobuf_fields(mut buf: &[u8]) -> Vec<(u64, Vec<u8>)> {
+        fn read_varint(buf: &mut &[u8]) -> u64 {
+            let (mut value, mut shift) = (0, 0);
+            loop {
+                let byte = buf[0];
+                *buf = &buf[1..];
+                value |= u64::from(byte & 0x7f) << shift;
+                if byte < 0x80 {
+                    return value;
+                }
+                shift += 7;
+            }
+        }
+
+        let mut fields = Vec::new();
+        while !buf.is_empty() {
+            let key = read_varint(&mut buf);
+            let value = match key & 7 {
+                0 => read_varint(&mut buf).to_string().into_bytes(),
+                _ => {
+                    let len = read_varint(&mut buf) as usize;
+                    let (value, rest) = buf.split_at(len);
+                    buf = rest;
+                    value.to_vec()
+                }
+            };
+            fields.push((key >> 3, value));
+        }
+        fields
+    }
+
+    #[test]
+    fn pushes_snappy_protobuf() {
+        let (url, requests) = serve(Vec::new());
+        let mut output = LokiOutput::new(format!("{}/", url)).with_format(LokiFormat::Protobuf);
+
+        write_record(&mut output, Level::Warn, "disk \"full\"", "api");
+        drop(output);
+
+        let request = requests.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(request.path, PUSH_PATH);
+        assert_eq!(
+            request.header("content-type"),
+            Some("application/x-protobuf")
+        );
+
+        let body = snap::raw::Decoder::new()
+            .decompress_vec(&request.body)
+            .unwrap();
+        let streams = protobuf_fields(&body);
+        assert_eq!(streams.len(), 1);
+        let stream = protobuf_fields(&streams[0].1);
+        assert_eq!(stream[0], (1, b"{level=\"warn\"}".to_vec()));
+        let entry = protobuf_fields(&stream[1].1);
+        assert_eq!(entry[1], (2, b"msg=disk \"full\"".to_vec()));
+        let timestamp = protobuf_fields(&entry[0].1);
+        assert!(
+            String::from_utf8_lossy(&timestamp[0].1)
+                .parse::<u64>()
+                .unwrap()
+                > 1_600_000_000
+        );
+    }
+}
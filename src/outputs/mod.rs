@@ -2,10 +2,289 @@
 //! Outputs tell `tacit` where to send the logged information.
 //! Examples include the console, a file, a database, etc.
 
+mod compression;
+mod file_output;
+mod gelf_output;
+#[cfg(feature = "http")]
+mod http_output;
+#[cfg(target_os = "linux")]
+mod journald_output;
+#[cfg(feature = "loki")]
+mod loki_output;
+mod rolling_file_output;
 mod simple_console_output;
+mod split_console_output;
+mod stderr_output;
+mod syslog_output;
+mod tcp_output;
+mod tee_output;
+mod timed_file_output;
+mod udp_output;
 
+pub use compression::Compression;
+pub use file_output::*;
+pub use gelf_output::*;
+#[cfg(feature = "http")]
+pub use http_output::*;
+#[cfg(target_os = "linux")]
+pub use journald_output::*;
+#[cfg(feature = "loki")]
+pub use loki_output::*;
+pub use rolling_file_output::*;
 pub use simple_console_output::*;
-use std::io::Write;
+pub use split_console_output::*;
+pub use stderr_output::*;
+pub use syslog_output::*;
+pub use tcp_output::*;
+pub use tee_output::*;
+pub use timed_file_output::*;
+pub use udp_output::*;
+
+use crate::{ErrorReporter, Property, StaticProperty};
+use log::{Level, Metadata, Record};
+use std::io::{self, Write};
+
+/// Describes the record an entry was rendered from, letting outputs route entries by
+/// level, target or properties.
+///
+/// The message and structured fields are only collected for outputs that set
+/// `TacitOutput::WANTS_FIELDS`, for others they are empty.
+#[derive(Clone, Debug)]
+pub struct RecordInfo {
+    level: Level,
+    target: String,
+    module_path: Option<String>,
+    file: Option<String>,
+    line: Option<u32>,
+    message: String,
+    fields: Vec<(String, StaticProperty)>,
+}
+
+impl RecordInfo {
+    /// Collect the message, the logger's properties and the record's key value pairs.
+    /// Null properties are skipped when `ignore_empty_props` is set.
+    pub(crate) fn with_fields(
+        mut self,
+        record: &Record,
+        default_props: &[(String, Property)],
+        ignore_empty_props: bool,
+    ) -> Self {
+        self.message = format!("{}", record.args());
+
+        for (name, prop) in default_props {
+            let value = prop.value(record);
+            if ignore_empty_props && value == StaticProperty::Null {
+                continue;
+            }
+            self.fields.push((name.clone(), value));
+        }
+
+        #[cfg(feature = "kv")]
+        for (key, value) in crate::formatters::kv_strings(record) {
+            self.fields.push((key, StaticProperty::String(value)));
+        }
+
+        self
+    }
+
+    /// Level of the record.
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    /// Target of the record.
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// Module the record was logged from.
+    pub fn module_path(&self) -> Option<&str> {
+        self.module_path.as_deref()
+    }
+
+    /// Source file the record was logged from.
+    pub fn file(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+
+    /// Source line the record was logged from.
+    pub fn line(&self) -> Option<u32> {
+        self.line
+    }
+
+    /// The log message, without any formatting.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The logger's properties followed by the record's key value pairs, evaluated for
+    /// this record.
+    pub fn fields(&self) -> &[(String, StaticProperty)] {
+        &self.fields
+    }
+
+    /// Value of the property or key value pair `name`.
+    pub fn field(&self, name: &str) -> Option<&StaticProperty> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value)
+    }
+}
+
+impl From<&Record<'_>> for RecordInfo {
+    fn from(record: &Record<'_>) -> Self {
+        Self {
+            level: record.level(),
+            target: record.target().to_string(),
+            module_path: record.module_path().map(String::from),
+            file: record.file().map(String::from),
+            line: record.line(),
+            message: String::new(),
+            fields: Vec::new(),
+        }
+    }
+}
 
 /// Defines output implementations
-pub trait TacitOutput: Default + Write + Send + Sync {}
+pub trait TacitOutput: Default + Write + Send + Sync {
+    /// Whether `write_entry` needs the message and structured fields of `RecordInfo`,
+    /// which are otherwise not collected.
+    const WANTS_FIELDS: bool = false;
+
+    /// Write an entry rendered from the record described by `info`. Outputs that route
+    /// entries, e.g. by level, override this, the rest write the entry as is.
+    fn write_entry(&mut self, info: &RecordInfo, entry: &[u8]) -> io::Result<()> {
+        let _ = info;
+        self.write_all(entry)
+    }
+
+    /// Whether records like `metadata` are left out, checked before the output is locked.
+    /// Outputs whose own dependencies log while writing skip those records, which would
+    /// otherwise feed back into the output.
+    fn ignores(metadata: &Metadata) -> bool {
+        let _ = metadata;
+        false
+    }
+
+    /// Whether entries at `level` are written to a terminal, which formatters use to
+    /// resolve `ColorChoice::Auto`.
+    fn is_terminal(&self, level: Level) -> bool {
+        let _ = level;
+        false
+    }
+
+    /// Called once the output belongs to a logger, with where to report failures that do
+    /// not surface from a write, e.g. those of a background thread. They are handled by
+    /// the logger's `ErrorPolicy`.
+    fn set_error_reporter(&mut self, reporter: ErrorReporter) {
+        let _ = reporter;
+    }
+
+    /// Flush, including entries an output holds back on purpose, e.g. a batch that is not
+    /// full yet. Called when the logger is flushed, while the async writer only calls
+    /// `flush` after writing what it had queued.
+    fn force_flush(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+
+    /// Flush all pending entries and wait for any background work to finish, called when
+    /// the logger shuts down. Outputs whose flush leaves work running override this.
+    fn close(&mut self) -> io::Result<()> {
+        self.force_flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Logger, SimpleFormatter};
+    use log::Log;
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct InfoOutput(Arc<Mutex<Vec<(RecordInfo, String)>>>);
+
+    impl TacitOutput for InfoOutput {
+        const WANTS_FIELDS: bool = true;
+
+        fn write_entry(&mut self, info: &RecordInfo, entry: &[u8]) -> io::Result<()> {
+            let entry = String::from_utf8_lossy(entry).into_owned();
+            self.0.lock().push((info.clone(), entry));
+            Ok(())
+        }
+    }
+
+    impl Write for InfoOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn passes_record_metadata_and_fields() {
+        let output = InfoOutput::default();
+        let logger = Logger::new(output.clone(), SimpleFormatter::default())
+            .with_prop(String::from("host"), "web-1".into())
+            .with_prop(String::from("none"), StaticProperty::Null)
+            .with_fn_prop(String::from("level"), |rec| rec.level().to_string().into())
+            .with_ignore_empty_props();
+
+        logger.log(
+            &Record::builder()
+                .args(format_args!("connection lost"))
+                .level(Level::Error)
+                .target("my_app::net")
+                .module_path(Some("my_app::net::tcp"))
+                .file(Some("src/net/tcp.rs"))
+                .line(Some(42))
+                .build(),
+        );
+
+        let infos = output.0.lock();
+        let info = &infos[0].0;
+        assert_eq!(info.level(), Level::Error);
+        assert_eq!(info.target(), "my_app::net");
+        assert_eq!(info.module_path(), Some("my_app::net::tcp"));
+        assert_eq!(info.file(), Some("src/net/tcp.rs"));
+        assert_eq!(info.line(), Some(42));
+        assert_eq!(info.message(), "connection lost");
+        assert_eq!(
+            info.fields(),
+            &[
+                (String::from("host"), "web-1".into()),
+                (String::from("level"), "ERROR".into()),
+            ]
+        );
+        assert_eq!(info.field("level"), Some(&"ERROR".into()));
+    }
+
+    #[test]
+    fn evaluates_function_props_once() {
+        use std::sync::atomic::{AtomicI64, Ordering};
+
+        static CALLS: AtomicI64 = AtomicI64::new(0);
+        let output = InfoOutput::default();
+        let logger = Logger::new(output.clone(), SimpleFormatter::default())
+            .with_fn_prop(String::from("call"), |_| {
+                (CALLS.fetch_add(1, Ordering::SeqCst) + 1).into()
+            });
+
+        for _ in 0..2 {
+            logger.log(&Record::builder().args(format_args!("hi")).build());
+        }
+
+        assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+        let infos = output.0.lock();
+        for (n, (info, entry)) in infos.iter().enumerate() {
+            let call = n as i64 + 1;
+            assert_eq!(info.field("call"), Some(&call.into()));
+            assert!(entry.contains(&format!("call={}", call)), "{}", entry);
+        }
+    }
+}
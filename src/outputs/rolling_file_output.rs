@@ -0,0 +1,446 @@
+//! # Rolling File Output
+//! Append all output to a file on disk, rotating it once it grows past a size
+//! threshold.
+//!
+//! Rotated files are kept next to the active file as archives, named either by
+//! number (`app.log.1` being the newest) or by the time of rotation
+//! (`app.log.20210329T151625.683`). Retention limits on the number of archives
+//! and their total size keep the disk from filling up. Archives can optionally
+//! be compressed in the background once rotated, and are only pruned once
+//! compressed, so the size limit applies to their compressed size.
+
+use super::{compression::Compressor, Compression, FileOutput, TacitOutput};
+use chrono::{TimeZone, Utc};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// Default size at which the file is rotated, 10 MiB.
+const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Default number of archives retained.
+const DEFAULT_MAX_ARCHIVES: usize = 5;
+
+/// Format used for the suffix of `ArchiveNaming::Timestamped` archives.
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.3f";
+
+/// How rotated files are named.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveNaming {
+    /// `app.log.1`, `app.log.2`, ... with `1` being the most recent.
+    Numbered,
+    /// `app.log.20210329T151625.683`, the UTC time of rotation.
+    Timestamped,
+}
+
+pub struct RollingFileOutput {
+    file: FileOutput,
+    max_bytes: u64,
+    naming: ArchiveNaming,
+    max_archives: Option<usize>,
+    max_archive_bytes: Option<u64>,
+    compressor: Compressor,
+    size: Option<u64>,
+    line_start: bool,
+}
+
+/// Where archives are kept and how many of them are retained, handed to the compression
+/// thread so archives are only pruned once compressed.
+struct Retention {
+    path: PathBuf,
+    naming: ArchiveNaming,
+    max_archives: Option<usize>,
+    max_archive_bytes: Option<u64>,
+}
+
+/// A rotated file found on disk.
+struct Archive {
+    key: u64,
+    path: PathBuf,
+    extension: &'static str,
+}
+
+impl Default for RollingFileOutput {
+    fn default() -> Self {
+        Self::new(FileOutput::default())
+    }
+}
+
+impl RollingFileOutput {
+    pub fn new(file: FileOutput) -> Self {
+        Self {
+            file,
+            max_bytes: DEFAULT_MAX_BYTES,
+            naming: ArchiveNaming::Numbered,
+            max_archives: Some(DEFAULT_MAX_ARCHIVES),
+            max_archive_bytes: None,
+            compressor: Compressor::default(),
+            size: None,
+            line_start: true,
+        }
+    }
+
+    /// Set the size in bytes past which the file is rotated.
+    pub fn set_max_bytes(&mut self, max_bytes: u64) {
+        self.max_bytes = max_bytes;
+    }
+
+    /// Set the size in bytes past which the file is rotated. Useful for chaining operations.
+    #[must_use]
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.set_max_bytes(max_bytes);
+        self
+    }
+
+    /// Set how rotated files are named.
+    pub fn set_naming(&mut self, naming: ArchiveNaming) {
+        self.naming = naming;
+    }
+
+    /// Set how rotated files are named. Useful for chaining operations.
+    #[must_use]
+    pub fn with_naming(mut self, naming: ArchiveNaming) -> Self {
+        self.set_naming(naming);
+        self
+    }
+
+    /// Keep at most `max_archives` rotated files, `None` keeps them all.
+    pub fn set_max_archives(&mut self, max_archives: Option<usize>) {
+        self.max_archives = max_archives;
+    }
+
+    /// Keep at most `max_archives` rotated files, `None` keeps them all. Useful for chaining
+    /// operations.
+    #[must_use]
+    pub fn with_max_archives(mut self, max_archives: Option<usize>) -> Self {
+        self.set_max_archives(max_archives);
+        self
+    }
+
+    /// Delete the oldest rotated files once together they exceed `max_archive_bytes`.
+    pub fn set_max_archive_bytes(&mut self, max_archive_bytes: Option<u64>) {
+        self.max_archive_bytes = max_archive_bytes;
+    }
+
+    /// Delete the oldest rotated files once together they exceed `max_archive_bytes`.
+    /// Useful for chaining operations.
+    #[must_use]
+    pub fn with_max_archive_bytes(mut self, max_archive_bytes: Option<u64>) -> Self {
+        self.set_max_archive_bytes(max_archive_bytes);
+        self
+    }
+
+    /// Set the compression applied to archives after rotation.
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compressor.set_compression(compression);
+    }
+
+    /// Set the compression applied to archives after rotation. Useful for chaining operations.
+    #[must_use]
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.set_compression(compression);
+        self
+    }
+
+    /// Rotate the current file now, regardless of its size. Does nothing if the file does
+    /// not exist, as there is nothing to archive.
+    pub fn rotate(&mut self) -> io::Result<()> {
+        self.file.close()?;
+        // archives are renamed and pruned below, which must not race their compression
+        self.compressor.wait();
+
+        self.size = Some(0);
+        if !self.file.path().exists() {
+            return Ok(());
+        }
+
+        let archive = match self.naming {
+            ArchiveNaming::Numbered => {
+                for archive in self.archives()?.into_iter().rev() {
+                    let suffix = format!("{}{}", archive.key + 1, archive.extension);
+                    fs::rename(archive.path, self.archive_path(&suffix))?;
+                }
+                self.archive_path("1")
+            }
+            ArchiveNaming::Timestamped => {
+                let stamp = Utc::now().format(TIMESTAMP_FORMAT).to_string();
+                let mut archive = self.archive_path(&stamp);
+                let mut attempt = 1;
+                while archive.exists() {
+                    archive = self.archive_path(&format!("{}-{}", stamp, attempt));
+                    attempt += 1;
+                }
+                archive
+            }
+        };
+
+        fs::rename(self.file.path(), &archive)?;
+
+        let retention = self.retention();
+        self.compressor
+            .compress_and_prune(archive, move || retention.prune())
+    }
+
+    fn retention(&self) -> Retention {
+        Retention {
+            path: self.file.path().to_path_buf(),
+            naming: self.naming,
+            max_archives: self.max_archives,
+            max_archive_bytes: self.max_archive_bytes,
+        }
+    }
+
+    /// Existing archives for the configured naming scheme, most recent first.
+    fn archives(&self) -> io::Result<Vec<Archive>> {
+        self.retention().archives()
+    }
+
+    fn archive_path(&self, suffix: &str) -> PathBuf {
+        let mut name = self.file.path().as_os_str().to_owned();
+        name.push(".");
+        name.push(suffix);
+        PathBuf::from(name)
+    }
+
+    fn current_size(&mut self) -> io::Result<u64> {
+        match self.size {
+            Some(size) => Ok(size),
+            None => {
+                let size = self.file.file()?.metadata()?.len();
+                self.size = Some(size);
+                Ok(size)
+            }
+        }
+    }
+}
+
+impl Retention {
+    /// Delete archives beyond the configured retention limits.
+    fn prune(&self) -> io::Result<()> {
+        let mut kept = 0;
+        let mut kept_bytes = 0;
+
+        for Archive { path, .. } in self.archives()? {
+            let len = fs::metadata(&path)?.len();
+            let over_count = self.max_archives.is_some_and(|max| kept >= max);
+            let over_bytes = self
+                .max_archive_bytes
+                .is_some_and(|max| kept_bytes + len > max);
+
+            if over_count || over_bytes {
+                fs::remove_file(&path)?;
+            } else {
+                kept += 1;
+                kept_bytes += len;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Existing archives for the configured naming scheme, most recent first.
+    fn archives(&self) -> io::Result<Vec<Archive>> {
+        let path = &self.path;
+        let prefix = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => format!("{}.", name),
+            None => return Ok(Vec::new()),
+        };
+        let dir = match path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            Some(dir) => dir,
+            None => Path::new("."),
+        };
+
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+
+        let mut archives = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name();
+            let suffix = match name.to_str().and_then(|name| name.strip_prefix(&prefix)) {
+                Some(suffix) => suffix,
+                None => continue,
+            };
+
+            let (suffix, extension) = Compression::split_extension(suffix);
+            if let Some(key) = self.archive_key(suffix) {
+                archives.push(Archive {
+                    key,
+                    path: entry.path(),
+                    extension,
+                });
+            }
+        }
+
+        archives.sort_by_key(|archive| archive.key);
+        if self.naming == ArchiveNaming::Timestamped {
+            archives.reverse();
+        }
+
+        Ok(archives)
+    }
+
+    /// Sort key of an archive from the suffix following the file name, if it is one.
+    fn archive_key(&self, suffix: &str) -> Option<u64> {
+        match self.naming {
+            ArchiveNaming::Numbered => suffix.parse().ok(),
+            ArchiveNaming::Timestamped => {
+                let (stamp, attempt) = match suffix.split_once('-') {
+                    Some((stamp, attempt)) => (stamp, attempt.parse().ok()?),
+                    None => (suffix, 0),
+                };
+                let time = chrono::NaiveDateTime::parse_from_str(stamp, TIMESTAMP_FORMAT).ok()?;
+                let millis = Utc.from_utc_datetime(&time).timestamp_millis() as u64;
+                Some(millis * 1000 + attempt)
+            }
+        }
+    }
+}
+
+impl TacitOutput for RollingFileOutput {
+    fn close(&mut self) -> io::Result<()> {
+        self.compressor.wait();
+        self.file.flush()
+    }
+}
+
+impl Write for RollingFileOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // only rotate between lines, so a single entry never spans two files
+        if self.line_start && self.current_size()? >= self.max_bytes {
+            self.rotate()?;
+        }
+
+        let written = self.file.write(buf)?;
+        self.size = Some(self.current_size()? + written as u64);
+        if written > 0 {
+            self.line_start = buf[written - 1] == b'\n';
+        }
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tacit-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("test.log")
+    }
+
+    fn sibling(path: &Path, suffix: &str) -> PathBuf {
+        PathBuf::from(format!("{}.{}", path.display(), suffix))
+    }
+
+    #[test]
+    fn rotates_numbered_with_retention() {
+        let path = temp_path("rolling-numbered");
+        let mut output = RollingFileOutput::new(FileOutput::new(&path))
+            .with_max_bytes(5)
+            .with_max_archives(Some(2));
+
+        for line in &["line one", "line two", "line three", "line four"] {
+            writeln!(output, "{}", line).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "line four\n");
+        assert_eq!(
+            fs::read_to_string(sibling(&path, "1")).unwrap(),
+            "line three\n"
+        );
+        assert_eq!(
+            fs::read_to_string(sibling(&path, "2")).unwrap(),
+            "line two\n"
+        );
+        assert!(!sibling(&path, "3").exists());
+    }
+
+    #[test]
+    fn rotates_timestamped_with_byte_cap() {
+        let path = temp_path("rolling-timestamped");
+        let mut output = RollingFileOutput::new(FileOutput::new(&path))
+            .with_naming(ArchiveNaming::Timestamped)
+            .with_max_bytes(1)
+            .with_max_archives(None)
+            .with_max_archive_bytes(Some(4));
+
+        for line in &["a", "b", "c", "d"] {
+            writeln!(output, "{}", line).unwrap();
+        }
+
+        let archives = output.archives().unwrap();
+        assert_eq!(archives.len(), 2);
+        assert_eq!(fs::read_to_string(&archives[0].path).unwrap(), "c\n");
+        assert_eq!(fs::read_to_string(&archives[1].path).unwrap(), "b\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "d\n");
+    }
+
+    #[test]
+    fn skips_rotating_a_missing_file() {
+        let path = temp_path("rolling-missing");
+        let mut output = RollingFileOutput::new(FileOutput::new(&path));
+
+        writeln!(output, "only").unwrap();
+        output.rotate().unwrap();
+        output.rotate().unwrap();
+
+        assert_eq!(fs::read_to_string(sibling(&path, "1")).unwrap(), "only\n");
+        assert!(!sibling(&path, "2").exists());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn prunes_by_compressed_size() {
+        let path = temp_path("rolling-gzip-bytes");
+        let mut output = RollingFileOutput::new(FileOutput::new(&path))
+            .with_max_bytes(1)
+            .with_max_archive_bytes(Some(200))
+            .with_compression(Compression::Gzip);
+
+        // each line compresses to well below the cap, but is far larger uncompressed
+        for _ in 0..3 {
+            writeln!(output, "{}", "a".repeat(1000)).unwrap();
+        }
+        output.close().unwrap();
+
+        assert!(sibling(&path, "1.gz").exists());
+        assert!(sibling(&path, "2.gz").exists());
+    }
+
+    #[test]
+    fn compresses_archives() {
+        use std::io::Read;
+
+        let path = temp_path("rolling-gzip");
+        let mut output = RollingFileOutput::new(FileOutput::new(&path))
+            .with_max_bytes(1)
+            .with_compression(Compression::Gzip);
+
+        for line in &["first", "second", "third"] {
+            writeln!(output, "{}", line).unwrap();
+        }
+        output.close().unwrap();
+
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(fs::File::open(sibling(&path, "2.gz")).unwrap())
+            .read_to_string(&mut decoded)
+            .unwrap();
+
+        assert_eq!(decoded, "first\n");
+        assert!(sibling(&path, "1.gz").exists());
+        assert!(!sibling(&path, "1").exists());
+        assert!(!sibling(&path, "2").exists());
+    }
+}
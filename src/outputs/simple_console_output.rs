@@ -1,13 +1,19 @@
 //! # Simple Console Output
-//! Write all output to the console (stdout).
+//! Write all output to the console (stdout). Formatters such as `SimpleFormatter`
+//! and `PatternFormatter` can color entries for it with `with_color`.
 
 use super::TacitOutput;
-use std::io::{stdout, Write};
+use log::Level;
+use std::io::{stdout, IsTerminal, Write};
 
 #[derive(Default)]
 pub struct SimpleConsoleOutput {}
 
-impl TacitOutput for SimpleConsoleOutput {}
+impl TacitOutput for SimpleConsoleOutput {
+    fn is_terminal(&self, _level: Level) -> bool {
+        stdout().is_terminal()
+    }
+}
 
 impl Write for SimpleConsoleOutput {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
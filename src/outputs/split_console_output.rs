@@ -0,0 +1,127 @@
+//! # Split Console Output
+//! Write entries to the console, sending warnings and errors to stderr and
+//! everything else to stdout. The level at which entries move to stderr is
+//! configurable. Bytes written without a record, through `Write`, go to stdout.
+
+use super::{RecordInfo, TacitOutput};
+use log::{Level, LevelFilter};
+use std::io::{self, stderr, stdout, IsTerminal, Write};
+
+pub struct SplitConsoleOutput {
+    stderr_level: LevelFilter,
+    stdout: Box<dyn Write + Send + Sync>,
+    stderr: Box<dyn Write + Send + Sync>,
+}
+
+impl Default for SplitConsoleOutput {
+    fn default() -> Self {
+        Self {
+            stderr_level: LevelFilter::Warn,
+            stdout: Box::new(stdout()),
+            stderr: Box::new(stderr()),
+        }
+    }
+}
+
+impl SplitConsoleOutput {
+    /// Send entries at `level` or more severe to stderr, `LevelFilter::Off` sends
+    /// everything to stdout.
+    pub fn set_stderr_level(&mut self, level: LevelFilter) {
+        self.stderr_level = level;
+    }
+
+    /// Send entries at `level` or more severe to stderr, `LevelFilter::Off` sends
+    /// everything to stdout. Useful for chaining operations.
+    #[must_use]
+    pub fn with_stderr_level(mut self, level: LevelFilter) -> Self {
+        self.set_stderr_level(level);
+        self
+    }
+
+    /// Write to other streams than the console's, so tests can see where entries went.
+    #[cfg(test)]
+    fn with_streams<O, E>(mut self, stdout: O, stderr: E) -> Self
+    where
+        O: Write + Send + Sync + 'static,
+        E: Write + Send + Sync + 'static,
+    {
+        self.stdout = Box::new(stdout);
+        self.stderr = Box::new(stderr);
+        self
+    }
+
+    fn is_stderr(&self, level: Level) -> bool {
+        level <= self.stderr_level
+    }
+}
+
+impl TacitOutput for SplitConsoleOutput {
+    fn is_terminal(&self, level: Level) -> bool {
+        if self.is_stderr(level) {
+            stderr().is_terminal()
+        } else {
+            stdout().is_terminal()
+        }
+    }
+
+    fn write_entry(&mut self, info: &RecordInfo, entry: &[u8]) -> io::Result<()> {
+        if self.is_stderr(info.level()) {
+            self.stderr.write_all(entry)
+        } else {
+            self.stdout.write_all(entry)
+        }
+    }
+}
+
+impl Write for SplitConsoleOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stdout.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let out = self.stdout.flush();
+        let err = self.stderr.flush();
+        out.and(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::SharedOutput;
+    use log::Record;
+    #[test]
+    fn routes_by_level() {
+        let output = SplitConsoleOutput::default();
+        assert!(output.is_stderr(Level::Error));
+        assert!(output.is_stderr(Level::Warn));
+        assert!(!output.is_stderr(Level::Info));
+        assert!(!output.is_stderr(Level::Trace));
+
+        let output = SplitConsoleOutput::default().with_stderr_level(LevelFilter::Off);
+        assert!(!output.is_stderr(Level::Error));
+    }
+
+    #[test]
+    fn writes_each_level_to_its_stream() {
+        let out = SharedOutput::default();
+        let err = SharedOutput::default();
+        let mut output = SplitConsoleOutput::default()
+            .with_stderr_level(LevelFilter::Warn)
+            .with_streams(out.clone(), err.clone());
+
+        for level in Level::iter() {
+            let info = RecordInfo::from(&Record::builder().level(level).build());
+            output
+                .write_entry(&info, format!("{}\n", level).as_bytes())
+                .unwrap();
+        }
+        output.write_all(b"plain\n").unwrap();
+        output.flush().unwrap();
+
+        assert_eq!(err.contents(), "ERROR\nWARN\n");
+        assert_eq!(out.contents(), "INFO\nDEBUG\nTRACE\nplain\n");
+        assert_eq!(out.flushes(), 1);
+        assert_eq!(err.flushes(), 1);
+    }
+}
@@ -0,0 +1,27 @@
+//! # Stderr Output
+//! Write all output to the console's standard error (stderr).
+
+use super::TacitOutput;
+use log::Level;
+use std::io::{stderr, IsTerminal, Write};
+
+#[derive(Default)]
+pub struct StderrOutput {}
+
+impl TacitOutput for StderrOutput {
+    fn is_terminal(&self, _level: Level) -> bool {
+        stderr().is_terminal()
+    }
+}
+
+impl Write for StderrOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut stderr = stderr();
+        stderr.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let mut stderr = stderr();
+        stderr.flush()
+    }
+}
@@ -0,0 +1,534 @@
+//! # Syslog Output
+//! Send entries to a syslog daemon such as rsyslog, formatted according to
+//! RFC 5424 or the older BSD format of RFC 3164.
+//!
+//! ```sh
+//! <14>1 2021-03-29T15:49:16.425441Z web-1 my_app 4242 - [tacit@32473 level="INFO"] msg="logging a thing"
+//! ```
+//!
+//! Entries are written to the local `/dev/log` socket by default, or to any
+//! unix datagram socket, UDP or TCP address. TCP uses octet counted framing
+//! (RFC 6587). Connections are made lazily, and a failed TCP connection is
+//! reopened on the next entry.
+//!
+//! The rendered entry, without its trailing newline, becomes the syslog MSG. The
+//! record's level maps to the syslog severity, and with RFC 5424 the logger's
+//! properties and key value pairs are written as STRUCTURED-DATA.
+
+use super::{RecordInfo, TacitOutput};
+use crate::StaticProperty;
+use chrono::{Local, SecondsFormat, Utc};
+use std::{
+    io::{self, Write},
+    net::{TcpStream, ToSocketAddrs, UdpSocket},
+};
+#[cfg(unix)]
+use std::{os::unix::net::UnixDatagram, path::PathBuf};
+
+/// Path of the local syslog socket.
+#[cfg(unix)]
+const DEFAULT_SOCKET: &str = "/dev/log";
+
+/// Default SD-ID, using the private enterprise number reserved for documentation.
+const DEFAULT_SD_ID: &str = "tacit@32473";
+
+/// Where syslog entries are sent.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SyslogTransport {
+    /// A local unix datagram socket, e.g. `/dev/log`.
+    #[cfg(unix)]
+    Unix(PathBuf),
+    /// A UDP address, e.g. `logs.example.com:514`.
+    Udp(String),
+    /// A TCP address, e.g. `logs.example.com:601`, using octet counted framing.
+    Tcp(String),
+}
+
+/// Syslog message format.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SyslogFormat {
+    /// RFC 5424, with STRUCTURED-DATA.
+    #[default]
+    Rfc5424,
+    /// RFC 3164, the BSD syslog format.
+    Rfc3164,
+}
+
+/// Syslog facility, identifying the kind of program logging.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Facility {
+    Kern,
+    #[default]
+    User,
+    Mail,
+    Daemon,
+    Auth,
+    Syslog,
+    Lpr,
+    News,
+    Uucp,
+    Cron,
+    Authpriv,
+    Ftp,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+impl Facility {
+    /// Numerical code of the facility.
+    pub fn code(self) -> u8 {
+        match self {
+            Self::Kern => 0,
+            Self::User => 1,
+            Self::Mail => 2,
+            Self::Daemon => 3,
+            Self::Auth => 4,
+            Self::Syslog => 5,
+            Self::Lpr => 6,
+            Self::News => 7,
+            Self::Uucp => 8,
+            Self::Cron => 9,
+            Self::Authpriv => 10,
+            Self::Ftp => 11,
+            Self::Local0 => 16,
+            Self::Local1 => 17,
+            Self::Local2 => 18,
+            Self::Local3 => 19,
+            Self::Local4 => 20,
+            Self::Local5 => 21,
+            Self::Local6 => 22,
+            Self::Local7 => 23,
+        }
+    }
+}
+
+/// Syslog severity for a log level.
+pub fn syslog_severity(level: log::Level) -> u8 {
+    match level {
+        log::Level::Error => 3,
+        log::Level::Warn => 4,
+        log::Level::Info => 6,
+        log::Level::Debug | log::Level::Trace => 7,
+    }
+}
+
+enum Connection {
+    #[cfg(unix)]
+    Unix(UnixDatagram),
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+}
+
+pub struct SyslogOutput {
+    transport: SyslogTransport,
+    format: SyslogFormat,
+    facility: Facility,
+    app_name: String,
+    hostname: String,
+    procid: String,
+    sd_id: String,
+    connection: Option<Connection>,
+}
+
+impl Default for SyslogOutput {
+    fn default() -> Self {
+        #[cfg(unix)]
+        let transport = SyslogTransport::Unix(PathBuf::from(DEFAULT_SOCKET));
+        #[cfg(not(unix))]
+        let transport = SyslogTransport::Udp(String::from("127.0.0.1:514"));
+
+        Self::new(transport)
+    }
+}
+
+impl SyslogOutput {
+    pub fn new(transport: SyslogTransport) -> Self {
+        let app_name = std::env::current_exe()
+            .ok()
+            .and_then(|exe| {
+                exe.file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+            })
+            .unwrap_or_else(|| String::from("tacit"));
+
+        Self {
+            transport,
+            format: SyslogFormat::default(),
+            facility: Facility::default(),
+            app_name,
+            hostname: hostname(),
+            procid: std::process::id().to_string(),
+            sd_id: String::from(DEFAULT_SD_ID),
+            connection: None,
+        }
+    }
+
+    /// Set the message format, RFC 5424 by default.
+    pub fn set_format(&mut self, format: SyslogFormat) {
+        self.format = format;
+    }
+
+    /// Set the message format, RFC 5424 by default. Useful for chaining operations.
+    #[must_use]
+    pub fn with_format(mut self, format: SyslogFormat) -> Self {
+        self.set_format(format);
+        self
+    }
+
+    /// Set the facility, `Facility::User` by default.
+    pub fn set_facility(&mut self, facility: Facility) {
+        self.facility = facility;
+    }
+
+    /// Set the facility, `Facility::User` by default. Useful for chaining operations.
+    #[must_use]
+    pub fn with_facility(mut self, facility: Facility) -> Self {
+        self.set_facility(facility);
+        self
+    }
+
+    /// Set the APP-NAME, or tag with RFC 3164, the executable's name by default.
+    pub fn set_app_name<S: Into<String>>(&mut self, app_name: S) {
+        self.app_name = app_name.into();
+    }
+
+    /// Set the APP-NAME, or tag with RFC 3164, the executable's name by default. Useful for
+    /// chaining operations.
+    #[must_use]
+    pub fn with_app_name<S: Into<String>>(mut self, app_name: S) -> Self {
+        self.set_app_name(app_name);
+        self
+    }
+
+    /// Set the HOSTNAME, the system's host name by default.
+    pub fn set_hostname<S: Into<String>>(&mut self, hostname: S) {
+        self.hostname = hostname.into();
+    }
+
+    /// Set the HOSTNAME, the system's host name by default. Useful for chaining operations.
+    #[must_use]
+    pub fn with_hostname<S: Into<String>>(mut self, hostname: S) -> Self {
+        self.set_hostname(hostname);
+        self
+    }
+
+    /// Set the PROCID, the process id by default.
+    pub fn set_procid<S: Into<String>>(&mut self, procid: S) {
+        self.procid = procid.into();
+    }
+
+    /// Set the PROCID, the process id by default. Useful for chaining operations.
+    #[must_use]
+    pub fn with_procid<S: Into<String>>(mut self, procid: S) -> Self {
+        self.set_procid(procid);
+        self
+    }
+
+    /// Set the SD-ID properties are written under, `tacit@32473` by default.
+    pub fn set_structured_data_id<S: Into<String>>(&mut self, sd_id: S) {
+        self.sd_id = sd_id.into();
+    }
+
+    /// Set the SD-ID properties are written under, `tacit@32473` by default. Useful for
+    /// chaining operations.
+    #[must_use]
+    pub fn with_structured_data_id<S: Into<String>>(mut self, sd_id: S) -> Self {
+        self.set_structured_data_id(sd_id);
+        self
+    }
+
+    fn connect(&self) -> io::Result<Connection> {
+        match &self.transport {
+            #[cfg(unix)]
+            SyslogTransport::Unix(path) => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(path)?;
+                Ok(Connection::Unix(socket))
+            }
+            SyslogTransport::Udp(address) => {
+                let address = address
+                    .to_socket_addrs()?
+                    .next()
+                    .ok_or_else(|| io::Error::from(io::ErrorKind::AddrNotAvailable))?;
+                let local = if address.is_ipv4() {
+                    "0.0.0.0:0"
+                } else {
+                    "[::]:0"
+                };
+                let socket = UdpSocket::bind(local)?;
+                socket.connect(address)?;
+                Ok(Connection::Udp(socket))
+            }
+            SyslogTransport::Tcp(address) => Ok(Connection::Tcp(TcpStream::connect(address)?)),
+        }
+    }
+
+    /// Render a syslog message for an entry.
+    fn message(&self, info: &RecordInfo, entry: &[u8]) -> Vec<u8> {
+        let pri = self.facility.code() * 8 + syslog_severity(info.level());
+        let msg = entry
+            .strip_suffix(b"\n")
+            .map(|msg| msg.strip_suffix(b"\r").unwrap_or(msg))
+            .unwrap_or(entry);
+
+        let mut message = match self.format {
+            SyslogFormat::Rfc5424 => format!(
+                "<{}>1 {} {} {} {} - {} ",
+                pri,
+                Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+                header_field(&self.hostname, 255),
+                header_field(&self.app_name, 48),
+                header_field(&self.procid, 128),
+                self.structured_data(info),
+            ),
+            SyslogFormat::Rfc3164 => {
+                let mut header = format!("<{}>{} ", pri, Local::now().format("%b %e %H:%M:%S"));
+                // the local daemon adds the host name itself
+                #[cfg(unix)]
+                let local = matches!(self.transport, SyslogTransport::Unix(_));
+                #[cfg(not(unix))]
+                let local = false;
+                if !local {
+                    header.push_str(&header_field(&self.hostname, 255));
+                    header.push(' ');
+                }
+                header.push_str(&format!(
+                    "{}[{}]: ",
+                    header_field(&self.app_name, 32),
+                    header_field(&self.procid, 128)
+                ));
+                header
+            }
+        }
+        .into_bytes();
+
+        message.extend_from_slice(msg);
+        message
+    }
+
+    /// STRUCTURED-DATA element holding the entry's fields, or `-` when there are none.
+    fn structured_data(&self, info: &RecordInfo) -> String {
+        let mut params = String::new();
+        for (name, value) in info.fields() {
+            let name: String = name
+                .chars()
+                .filter(|c| c.is_ascii_graphic() && !matches!(c, '=' | ']' | '"'))
+                .take(32)
+                .collect();
+            if name.is_empty() {
+                continue;
+            }
+
+            let value = match value {
+                StaticProperty::String(v) => v.to_string(),
+                StaticProperty::Number(v) => v.to_string(),
+                StaticProperty::Null => String::new(),
+            };
+            params.push_str(&format!(" {}=\"{}\"", name, escape_param(&value)));
+        }
+
+        if params.is_empty() {
+            String::from("-")
+        } else {
+            format!("[{}{}]", header_field(&self.sd_id, 32), params)
+        }
+    }
+
+    fn send(&mut self, message: &[u8]) -> io::Result<()> {
+        if self.connection.is_none() {
+            self.connection = Some(self.connect()?);
+        }
+
+        let result = match self.connection.as_mut() {
+            #[cfg(unix)]
+            Some(Connection::Unix(socket)) => socket.send(message).map(|_| ()),
+            Some(Connection::Udp(socket)) => socket.send(message).map(|_| ()),
+            Some(Connection::Tcp(stream)) => {
+                let mut framed = format!("{} ", message.len()).into_bytes();
+                framed.extend_from_slice(message);
+                stream.write_all(&framed)
+            }
+            None => Ok(()),
+        };
+
+        if result.is_err() {
+            // reconnect on the next entry
+            self.connection = None;
+        }
+        result
+    }
+}
+
+/// The system's host name, or `-` when it is unknown.
+pub(crate) fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| String::from("-"))
+}
+
+/// Header fields are printable ASCII without spaces, limited in length, and `-` when empty.
+fn header_field(value: &str, max: usize) -> String {
+    let field: String = value
+        .chars()
+        .filter(|c| c.is_ascii_graphic())
+        .take(max)
+        .collect();
+    if field.is_empty() {
+        String::from("-")
+    } else {
+        field
+    }
+}
+
+/// Escape a PARAM-VALUE, quotes, backslashes and closing brackets are escaped.
+fn escape_param(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl TacitOutput for SyslogOutput {
+    const WANTS_FIELDS: bool = true;
+
+    fn write_entry(&mut self, info: &RecordInfo, entry: &[u8]) -> io::Result<()> {
+        let message = self.message(info, entry);
+        self.send(&message)
+    }
+}
+
+impl Write for SyslogOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let info = RecordInfo::from(&log::Record::builder().level(log::Level::Info).build());
+        self.write_entry(&info, buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.connection.as_mut() {
+            Some(Connection::Tcp(stream)) => stream.flush(),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Logger, SimpleFormatter};
+    use log::{Level, Log, Record};
+    use std::{io::Read, net::TcpListener};
+
+    fn log_entry<L: Log>(logger: &L, message: &str) {
+        logger.log(
+            &Record::builder()
+                .args(format_args!("{}", message))
+                .level(Level::Error)
+                .build(),
+        );
+    }
+
+    fn configure(output: SyslogOutput) -> SyslogOutput {
+        output
+            .with_hostname("web-1")
+            .with_app_name("my app")
+            .with_procid("42")
+            .with_facility(Facility::Local0)
+    }
+
+    #[test]
+    fn sends_rfc5424_over_udp() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let output = configure(SyslogOutput::new(SyslogTransport::Udp(
+            server.local_addr().unwrap().to_string(),
+        )));
+        let logger = Logger::new(output, SimpleFormatter::default())
+            .with_prop(String::from("user"), "a \"b\" ]".into());
+
+        log_entry(&logger, "disk full");
+
+        let mut buf = [0; 1024];
+        let len = server.recv(&mut buf).unwrap();
+        let message = String::from_utf8_lossy(&buf[..len]).into_owned();
+        let (pri, rest) = message.split_once(' ').unwrap();
+        let (timestamp, rest) = rest.split_once(' ').unwrap();
+
+        assert_eq!(pri, "<131>1");
+        assert!(chrono::DateTime::parse_from_rfc3339(timestamp).is_ok());
+        assert_eq!(
+            rest,
+            "web-1 myapp 42 - [tacit@32473 user=\"a \\\"b\\\" \\]\"] user=\"a \\\"b\\\" ]\" msg=\"disk full\""
+        );
+    }
+
+    #[test]
+    fn frames_tcp_with_octet_counts() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let output = configure(SyslogOutput::new(SyslogTransport::Tcp(
+            server.local_addr().unwrap().to_string(),
+        )))
+        .with_format(SyslogFormat::Rfc3164);
+        let logger = Logger::new(output, SimpleFormatter::default());
+
+        log_entry(&logger, "first");
+        log_entry(&logger, "second");
+        drop(logger);
+
+        let mut received = String::new();
+        server
+            .accept()
+            .unwrap()
+            .0
+            .read_to_string(&mut received)
+            .unwrap();
+
+        let mut frames = Vec::new();
+        let mut rest = received.as_str();
+        while let Some((len, tail)) = rest.split_once(' ') {
+            let len: usize = len.parse().unwrap();
+            frames.push(&tail[..len]);
+            rest = &tail[len..];
+        }
+
+        assert_eq!(frames.len(), 2);
+        assert!(frames[0].starts_with("<131>"));
+        assert!(frames[0].ends_with(" web-1 myapp[42]: msg=first"));
+        assert!(frames[1].ends_with(" web-1 myapp[42]: msg=second"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sends_rfc3164_to_unix_socket() {
+        let path = std::env::temp_dir().join(format!("tacit-{}-syslog", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let server = UnixDatagram::bind(&path).unwrap();
+        let output = configure(SyslogOutput::new(SyslogTransport::Unix(path.clone())))
+            .with_format(SyslogFormat::Rfc3164);
+        let logger = Logger::new(output, SimpleFormatter::default());
+
+        log_entry(&logger, "local");
+
+        let mut buf = [0; 1024];
+        let len = server.recv(&mut buf).unwrap();
+        let message = String::from_utf8_lossy(&buf[..len]).into_owned();
+
+        assert!(message.starts_with("<131>"));
+        assert!(message.ends_with(" myapp[42]: msg=local"));
+        assert!(!message.contains("web-1"));
+        let _ = std::fs::remove_file(&path);
+    }
+}
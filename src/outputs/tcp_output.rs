@@ -0,0 +1,534 @@
+//! # TCP Output
+//! Stream entries to a log collector over TCP, e.g. JSON lines from the
+//! `JsonFormatter`.
+//!
+//! The connection is made lazily on the first entry, on a background thread so
+//! that an unreachable collector never holds up logging. Until it is made, or
+//! while it is lost, entries are buffered and reconnecting backs off
+//! exponentially. The buffer is bounded, once full the oldest entries are
+//! discarded and reported to the logger's `ErrorPolicy`. An entry that was only
+//! partially written is continued where it left off, so no bytes are sent twice.
+//!
+//! With the `tls` feature the stream can be wrapped in TLS, verified against the
+//! Mozilla root certificates or a custom `rustls::ClientConfig`.
+
+use super::TacitOutput;
+use crate::ErrorReporter;
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    net::{TcpStream, ToSocketAddrs},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+#[cfg(feature = "tls")]
+use std::{convert::TryFrom, sync::Arc};
+
+/// Default address used when no other is provided.
+const DEFAULT_ADDRESS: &str = "127.0.0.1:5170";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_MIN_BACKOFF: Duration = Duration::from_millis(100);
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(30);
+const DEFAULT_MAX_BUFFER: usize = 1024 * 1024;
+
+enum Stream {
+    Plain(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<rustls::StreamOwned<rustls::ClientConnection, TcpStream>>),
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(stream) => stream.write(buf),
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            Self::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+#[derive(Clone)]
+struct Tls {
+    config: Arc<rustls::ClientConfig>,
+    server_name: rustls::ServerName,
+}
+
+/// What is needed to connect, handed to the thread making the connection.
+#[derive(Clone)]
+struct Connector {
+    address: String,
+    timeout: Duration,
+    #[cfg(feature = "tls")]
+    tls: Option<Tls>,
+}
+
+impl Connector {
+    fn connect(&self) -> io::Result<Stream> {
+        let mut last_err = io::Error::from(io::ErrorKind::AddrNotAvailable);
+        for address in self.address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, self.timeout) {
+                Ok(stream) => {
+                    stream.set_write_timeout(Some(self.timeout))?;
+                    stream.set_nodelay(true)?;
+                    return self.wrap(stream);
+                }
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err)
+    }
+
+    #[cfg(feature = "tls")]
+    fn wrap(&self, stream: TcpStream) -> io::Result<Stream> {
+        match &self.tls {
+            Some(tls) => {
+                let connection =
+                    rustls::ClientConnection::new(tls.config.clone(), tls.server_name.clone())
+                        .map_err(io::Error::other)?;
+                Ok(Stream::Tls(Box::new(rustls::StreamOwned::new(
+                    connection, stream,
+                ))))
+            }
+            None => Ok(Stream::Plain(stream)),
+        }
+    }
+
+    #[cfg(not(feature = "tls"))]
+    fn wrap(&self, stream: TcpStream) -> io::Result<Stream> {
+        Ok(Stream::Plain(stream))
+    }
+}
+
+pub struct TcpOutput {
+    connector: Connector,
+    min_backoff: Duration,
+    max_backoff: Duration,
+    backoff: Duration,
+    next_attempt: Option<Instant>,
+    max_buffer: usize,
+    buffer: VecDeque<Vec<u8>>,
+    buffered: usize,
+    /// Bytes of the oldest buffered entry already written.
+    offset: usize,
+    stream: Option<Stream>,
+    connecting: Option<JoinHandle<io::Result<Stream>>>,
+    errors: ErrorReporter,
+}
+
+impl Default for TcpOutput {
+    fn default() -> Self {
+        Self::new(DEFAULT_ADDRESS)
+    }
+}
+
+impl TcpOutput {
+    /// Stream entries to `address`, e.g. `logs.example.com:5170`.
+    pub fn new<S: Into<String>>(address: S) -> Self {
+        Self {
+            connector: Connector {
+                address: address.into(),
+                timeout: DEFAULT_TIMEOUT,
+                #[cfg(feature = "tls")]
+                tls: None,
+            },
+            min_backoff: DEFAULT_MIN_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            backoff: DEFAULT_MIN_BACKOFF,
+            next_attempt: None,
+            max_buffer: DEFAULT_MAX_BUFFER,
+            buffer: VecDeque::new(),
+            buffered: 0,
+            offset: 0,
+            stream: None,
+            connecting: None,
+            errors: ErrorReporter::default(),
+        }
+    }
+
+    /// Set how long connecting and writing may take before giving up, 5 seconds by default.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.connector.timeout = timeout;
+    }
+
+    /// Set how long connecting and writing may take before giving up, 5 seconds by default.
+    /// Useful for chaining operations.
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.set_timeout(timeout);
+        self
+    }
+
+    /// Set the delay before the first reconnect attempt, doubled after every failed attempt
+    /// up to `max`. 100 milliseconds and 30 seconds by default.
+    pub fn set_backoff(&mut self, min: Duration, max: Duration) {
+        self.min_backoff = min;
+        self.max_backoff = max.max(min);
+        self.backoff = min;
+    }
+
+    /// Set the delay before the first reconnect attempt, doubled after every failed attempt
+    /// up to `max`. 100 milliseconds and 30 seconds by default. Useful for chaining operations.
+    #[must_use]
+    pub fn with_backoff(mut self, min: Duration, max: Duration) -> Self {
+        self.set_backoff(min, max);
+        self
+    }
+
+    /// Set how many bytes of entries are kept while disconnected, 1MiB by default.
+    pub fn set_max_buffer(&mut self, max_buffer: usize) {
+        self.max_buffer = max_buffer;
+    }
+
+    /// Set how many bytes of entries are kept while disconnected, 1MiB by default. Useful for
+    /// chaining operations.
+    #[must_use]
+    pub fn with_max_buffer(mut self, max_buffer: usize) -> Self {
+        self.set_max_buffer(max_buffer);
+        self
+    }
+
+    /// Wrap the stream in TLS, verifying the collector's certificate for `server_name`
+    /// against the Mozilla root certificates.
+    #[cfg(feature = "tls")]
+    pub fn set_tls(&mut self, server_name: &str) -> io::Result<()> {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+            rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+                anchor.subject,
+                anchor.spki,
+                anchor.name_constraints,
+            )
+        }));
+        let config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        self.set_tls_config(Arc::new(config), server_name)
+    }
+
+    /// Wrap the stream in TLS, verifying the collector's certificate for `server_name`
+    /// against the Mozilla root certificates. Useful for chaining operations.
+    #[cfg(feature = "tls")]
+    pub fn with_tls(mut self, server_name: &str) -> io::Result<Self> {
+        self.set_tls(server_name)?;
+        Ok(self)
+    }
+
+    /// Wrap the stream in TLS using a custom configuration, e.g. to trust a private
+    /// certificate authority or present a client certificate.
+    #[cfg(feature = "tls")]
+    pub fn set_tls_config(
+        &mut self,
+        config: Arc<rustls::ClientConfig>,
+        server_name: &str,
+    ) -> io::Result<()> {
+        let server_name = rustls::ServerName::try_from(server_name)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        self.connector.tls = Some(Tls {
+            config,
+            server_name,
+        });
+        Ok(())
+    }
+
+    /// Wrap the stream in TLS using a custom configuration, e.g. to trust a private
+    /// certificate authority or present a client certificate. Useful for chaining operations.
+    #[cfg(feature = "tls")]
+    pub fn with_tls_config(
+        mut self,
+        config: Arc<rustls::ClientConfig>,
+        server_name: &str,
+    ) -> io::Result<Self> {
+        self.set_tls_config(config, server_name)?;
+        Ok(self)
+    }
+
+    /// Keep an entry until it can be sent.
+    fn push(&mut self, entry: &[u8]) {
+        self.buffer.push_back(entry.to_vec());
+        self.buffered += entry.len();
+    }
+
+    /// Discard the oldest unsent entries beyond the bound and report them as lost. An
+    /// entry that is already partially written is kept.
+    fn trim(&mut self) {
+        let mut discarded = Vec::new();
+        let mut count = 0;
+        while self.buffered > self.max_buffer {
+            match self.buffer.remove(usize::from(self.offset > 0)) {
+                Some(entry) => {
+                    self.buffered -= entry.len();
+                    discarded.extend_from_slice(&entry);
+                    count += 1;
+                }
+                None => break,
+            }
+        }
+
+        if count > 0 {
+            let err = io::Error::other(format!(
+                "discarded {} entries while unable to reach {}",
+                count, self.connector.address
+            ));
+            self.errors.report(&err, Some(&discarded));
+        }
+    }
+
+    /// Whether there is a connection to write to. If not, an attempt is started in the
+    /// background once the backoff allows it, and taken over once it has finished or,
+    /// when `wait` is set, after waiting for it.
+    fn connected(&mut self, wait: bool) -> bool {
+        if self.stream.is_some() {
+            return true;
+        }
+
+        match self.connecting.take() {
+            Some(attempt) if !wait && !attempt.is_finished() => {
+                self.connecting = Some(attempt);
+                false
+            }
+            Some(attempt) => match attempt.join() {
+                Ok(Ok(stream)) => {
+                    self.stream = Some(stream);
+                    self.next_attempt = None;
+                    self.backoff = self.min_backoff;
+                    true
+                }
+                _ => {
+                    self.disconnect();
+                    false
+                }
+            },
+            None => {
+                if self.next_attempt.is_some_and(|at| Instant::now() < at) {
+                    return false;
+                }
+
+                let connector = self.connector.clone();
+                let attempt = thread::Builder::new()
+                    .name(String::from("tacit-tcp"))
+                    .spawn(move || connector.connect());
+                match attempt {
+                    Ok(attempt) => self.connecting = Some(attempt),
+                    Err(_) => {
+                        self.disconnect();
+                        return false;
+                    }
+                }
+                wait && self.connected(wait)
+            }
+        }
+    }
+
+    /// Send buffered entries once connected. A write that timed out is continued on the
+    /// same connection later, any other failure drops the connection.
+    fn send(&mut self) {
+        if !self.connected(false) {
+            return;
+        }
+
+        while let Some(entry) = self.buffer.front() {
+            let result = match self.stream.as_mut() {
+                Some(stream) => stream.write(&entry[self.offset..]),
+                None => return,
+            };
+
+            match result {
+                Ok(0) => {
+                    self.disconnect();
+                    return;
+                }
+                Ok(written) => self.offset += written,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return;
+                }
+                Err(_) => {
+                    self.disconnect();
+                    return;
+                }
+            }
+
+            if self.offset == entry.len() {
+                self.offset = 0;
+                if let Some(sent) = self.buffer.pop_front() {
+                    self.buffered -= sent.len();
+                }
+            }
+        }
+    }
+
+    /// Drop the connection and wait out the backoff before the next attempt.
+    fn disconnect(&mut self) {
+        self.stream = None;
+        self.next_attempt = Some(Instant::now() + self.backoff);
+        self.backoff = (self.backoff * 2).min(self.max_backoff);
+    }
+}
+
+impl TacitOutput for TcpOutput {
+    fn set_error_reporter(&mut self, reporter: ErrorReporter) {
+        self.errors = reporter;
+    }
+
+    /// Wait for a connection attempt in progress, then send what is buffered.
+    fn close(&mut self) -> io::Result<()> {
+        self.connected(true);
+        self.flush()
+    }
+}
+
+impl Write for TcpOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.push(buf);
+        self.send();
+        self.trim();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send();
+        match self.stream.as_mut() {
+            Some(stream) => stream.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error_policy::ErrorHandler, test_util::SharedOutput, ErrorPolicy};
+    use std::{
+        io::{BufRead, BufReader},
+        net::TcpListener,
+        sync::Arc,
+    };
+
+    fn free_address() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    #[test]
+    fn buffers_until_connected() {
+        let address = free_address();
+        let fallback = SharedOutput::default();
+        let mut output = TcpOutput::new(address.clone())
+            .with_backoff(Duration::from_millis(10), Duration::from_millis(10))
+            .with_max_buffer(13);
+        output.set_error_reporter(ErrorReporter::new(Arc::new(ErrorHandler::new(
+            ErrorPolicy::fallback(fallback.clone()),
+        ))));
+
+        // nothing is listening yet, so entries are buffered and the oldest discarded
+        output.write_all(b"lost\n").unwrap();
+        output.write_all(b"first\n").unwrap();
+        output.write_all(b"second\n").unwrap();
+        assert_eq!(output.buffer.len(), 2);
+        assert_eq!(fallback.contents(), "lost\n");
+
+        let listener = TcpListener::bind(&address).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        output.close().unwrap();
+        output.write_all(b"third\n").unwrap();
+        output.flush().unwrap();
+        drop(output);
+
+        let lines: Vec<String> = BufReader::new(listener.accept().unwrap().0)
+            .lines()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(lines, vec!["first", "second", "third"]);
+    }
+
+    #[test]
+    fn backs_off_exponentially() {
+        let mut output = TcpOutput::new(free_address())
+            .with_backoff(Duration::from_millis(50), Duration::from_millis(150));
+
+        output.write_all(b"entry\n").unwrap();
+        output.close().unwrap();
+        assert_eq!(output.backoff, Duration::from_millis(100));
+        let next_attempt = output.next_attempt;
+
+        // still waiting, so no new attempt is made
+        output.write_all(b"entry\n").unwrap();
+        assert!(output.connecting.is_none());
+        assert_eq!(output.next_attempt, next_attempt);
+
+        std::thread::sleep(Duration::from_millis(60));
+        output.write_all(b"entry\n").unwrap();
+        output.close().unwrap();
+        assert_eq!(output.backoff, Duration::from_millis(150));
+    }
+
+    #[test]
+    fn connects_in_the_background() {
+        // a non-routable address, where connecting only gives up after the timeout
+        let mut output = TcpOutput::new("10.255.255.1:9").with_timeout(Duration::from_secs(5));
+
+        let started = Instant::now();
+        output.write_all(b"first\n").unwrap();
+        output.write_all(b"second\n").unwrap();
+        output.flush().unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(output.buffer.len(), 2);
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn streams_over_tls() {
+        let cert = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+        let cert_der = rustls::Certificate(cert.serialize_der().unwrap());
+        let key_der = rustls::PrivateKey(cert.serialize_private_key_der());
+
+        let server_config = Arc::new(
+            rustls::ServerConfig::builder()
+                .with_safe_defaults()
+                .with_no_client_auth()
+                .with_single_cert(vec![cert_der.clone()], key_der)
+                .unwrap(),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let connection = rustls::ServerConnection::new(server_config).unwrap();
+            let stream = rustls::StreamOwned::new(connection, listener.accept().unwrap().0);
+            let mut line = String::new();
+            BufReader::new(stream).read_line(&mut line).unwrap();
+            line
+        });
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(&cert_der).unwrap();
+        let client_config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let mut output = TcpOutput::new(address)
+            .with_tls_config(Arc::new(client_config), "localhost")
+            .unwrap();
+
+        output.write_all(b"{\"msg\":\"secret\"}\n").unwrap();
+        output.close().unwrap();
+
+        assert_eq!(server.join().unwrap(), "{\"msg\":\"secret\"}\n");
+    }
+}
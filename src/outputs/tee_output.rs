@@ -0,0 +1,147 @@
+//! # Tee Output
+//! Write every entry to two outputs. Since formatters render an entry once before
+//! it is written, this sends the same rendering to both without formatting it
+//! twice. Tee outputs can be nested to reach more than two outputs.
+
+use super::{RecordInfo, TacitOutput};
+use crate::ErrorReporter;
+use log::{Level, Metadata};
+use std::io::{self, Write};
+
+#[derive(Default)]
+pub struct TeeOutput<A: TacitOutput, B: TacitOutput> {
+    first: A,
+    second: B,
+}
+
+impl<A: TacitOutput, B: TacitOutput> TeeOutput<A, B> {
+    pub fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+}
+
+impl<A: TacitOutput, B: TacitOutput> TacitOutput for TeeOutput<A, B> {
+    const WANTS_FIELDS: bool = A::WANTS_FIELDS || B::WANTS_FIELDS;
+
+    fn write_entry(&mut self, info: &RecordInfo, entry: &[u8]) -> io::Result<()> {
+        let first = self.first.write_entry(info, entry);
+        let second = self.second.write_entry(info, entry);
+        first.and(second)
+    }
+
+    fn ignores(metadata: &Metadata) -> bool {
+        A::ignores(metadata) || B::ignores(metadata)
+    }
+
+    /// Only when both outputs write to a terminal, so neither receives colors it can not
+    /// display.
+    fn is_terminal(&self, level: Level) -> bool {
+        self.first.is_terminal(level) && self.second.is_terminal(level)
+    }
+
+    fn set_error_reporter(&mut self, reporter: ErrorReporter) {
+        self.first.set_error_reporter(reporter.clone());
+        self.second.set_error_reporter(reporter);
+    }
+
+    fn force_flush(&mut self) -> io::Result<()> {
+        let first = self.first.force_flush();
+        let second = self.second.force_flush();
+        first.and(second)
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        let first = self.first.close();
+        let second = self.second.close();
+        first.and(second)
+    }
+}
+
+impl<A: TacitOutput, B: TacitOutput> Write for TeeOutput<A, B> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // always attempt both, so one failing output does not starve the other
+        let first = self.first.write_all(buf);
+        let second = self.second.write_all(buf);
+        first.and(second).map(|_| buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let first = self.first.flush();
+        let second = self.second.flush();
+        first.and(second)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util::SharedOutput, Logger, SimpleFormatter};
+    use log::{Level, Log, Record};
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    #[test]
+    fn writes_to_both() {
+        let first = SharedOutput::default();
+        let second = SharedOutput::default();
+        let logger = Logger::new(
+            TeeOutput::new(first.clone(), second.clone()),
+            SimpleFormatter::default(),
+        );
+
+        logger.log(
+            &Record::builder()
+                .args(format_args!("twice"))
+                .level(log::Level::Info)
+                .build(),
+        );
+        logger.flush();
+
+        assert_eq!(first.contents(), "msg=twice\n");
+        assert_eq!(second.contents(), "msg=twice\n");
+        assert_eq!((first.flushes(), second.flushes()), (1, 1));
+    }
+
+    #[derive(Clone, Default)]
+    struct LevelOutput(Arc<Mutex<Vec<Level>>>);
+
+    impl TacitOutput for LevelOutput {
+        fn write_entry(&mut self, info: &RecordInfo, _entry: &[u8]) -> io::Result<()> {
+            self.0.lock().push(info.level());
+            Ok(())
+        }
+    }
+
+    impl Write for LevelOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn passes_record_info_to_both() {
+        let first = LevelOutput::default();
+        let second = LevelOutput::default();
+        let logger = Logger::new(
+            TeeOutput::new(first.clone(), second.clone()),
+            SimpleFormatter::default(),
+        )
+        .with_level_filter(log::LevelFilter::Trace);
+
+        for level in [Level::Warn, Level::Debug].iter() {
+            logger.log(
+                &Record::builder()
+                    .args(format_args!("routed"))
+                    .level(*level)
+                    .build(),
+            );
+        }
+
+        assert_eq!(*first.0.lock(), vec![Level::Warn, Level::Debug]);
+        assert_eq!(*second.0.lock(), vec![Level::Warn, Level::Debug]);
+    }
+}
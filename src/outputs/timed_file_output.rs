@@ -0,0 +1,383 @@
+//! # Timed File Output
+//! Append all output to a file on disk, starting a new file every hour, day or
+//! week.
+//!
+//! The file name is built from a `strftime` style pattern such as
+//! `logs/app-%Y-%m-%d.log`, formatted with the start of the current period.
+//! Period boundaries can follow either UTC or the local time zone. Old files
+//! matching the pattern in the same directory are deleted once they are older
+//! than the configured maximum age. Finished files can optionally be compressed
+//! in the background.
+//!
+//! Creating the output fails with `InvalidInput` if the pattern is not a valid
+//! `strftime` format, or if its directory contains placeholders, e.g.
+//! `logs/%Y/app.log`, as files in such directories could never be pruned.
+
+use super::{compression::Compressor, Compression, FileOutput, TacitOutput};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDateTime, Timelike, Utc};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// Default file name pattern.
+const DEFAULT_PATTERN: &str = "tacit-%Y-%m-%d.log";
+
+/// How often a new file is started.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RotationPeriod {
+    Hourly,
+    Daily,
+    /// Weeks start on Monday.
+    Weekly,
+}
+
+/// Which clock period boundaries follow.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RotationClock {
+    Utc,
+    Local,
+}
+
+pub struct TimedFileOutput {
+    file: FileOutput,
+    pattern: String,
+    period: RotationPeriod,
+    clock: RotationClock,
+    max_age: Option<std::time::Duration>,
+    compressor: Compressor,
+    current: Option<NaiveDateTime>,
+    line_start: bool,
+}
+
+impl Default for TimedFileOutput {
+    fn default() -> Self {
+        Self::new(DEFAULT_PATTERN).expect("default pattern is valid")
+    }
+}
+
+impl TimedFileOutput {
+    /// Write to files named by a `strftime` pattern, failing with `InvalidInput` when it is
+    /// malformed or has placeholders in its directory.
+    pub fn new<S: Into<String>>(pattern: S) -> io::Result<Self> {
+        let pattern = pattern.into();
+        check_pattern(&pattern)?;
+
+        Ok(Self {
+            file: FileOutput::default(),
+            pattern,
+            period: RotationPeriod::Daily,
+            clock: RotationClock::Utc,
+            max_age: None,
+            compressor: Compressor::default(),
+            current: None,
+            line_start: true,
+        })
+    }
+
+    /// Set how often a new file is started.
+    pub fn set_period(&mut self, period: RotationPeriod) {
+        self.period = period;
+    }
+
+    /// Set how often a new file is started. Useful for chaining operations.
+    #[must_use]
+    pub fn with_period(mut self, period: RotationPeriod) -> Self {
+        self.set_period(period);
+        self
+    }
+
+    /// Set which clock period boundaries follow.
+    pub fn set_clock(&mut self, clock: RotationClock) {
+        self.clock = clock;
+    }
+
+    /// Set which clock period boundaries follow. Useful for chaining operations.
+    #[must_use]
+    pub fn with_clock(mut self, clock: RotationClock) -> Self {
+        self.set_clock(clock);
+        self
+    }
+
+    /// Delete files matching the pattern once they have not been written to for `max_age`.
+    pub fn set_max_age(&mut self, max_age: Option<std::time::Duration>) {
+        self.max_age = max_age;
+    }
+
+    /// Delete files matching the pattern once they have not been written to for `max_age`.
+    /// Useful for chaining operations.
+    #[must_use]
+    pub fn with_max_age(mut self, max_age: Option<std::time::Duration>) -> Self {
+        self.set_max_age(max_age);
+        self
+    }
+
+    /// Set the compression applied to files once their period has ended.
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compressor.set_compression(compression);
+    }
+
+    /// Set the compression applied to files once their period has ended. Useful for chaining
+    /// operations.
+    #[must_use]
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.set_compression(compression);
+        self
+    }
+
+    /// Create missing parent directories when opening a file, enabled by default.
+    pub fn set_create_dirs(&mut self, create_dirs: bool) {
+        self.file.set_create_dirs(create_dirs);
+    }
+
+    /// Create missing parent directories when opening a file. Useful for chaining operations.
+    #[must_use]
+    pub fn with_create_dirs(mut self, create_dirs: bool) -> Self {
+        self.set_create_dirs(create_dirs);
+        self
+    }
+
+    /// Set the permissions (e.g. `0o640`) applied to each file when it is opened.
+    /// Only has an effect on unix platforms.
+    pub fn set_mode(&mut self, mode: u32) {
+        self.file.set_mode(mode);
+    }
+
+    /// Set the permissions (e.g. `0o640`) applied to each file when it is opened.
+    /// Only has an effect on unix platforms. Useful for chaining operations.
+    #[must_use]
+    pub fn with_mode(mut self, mode: u32) -> Self {
+        self.set_mode(mode);
+        self
+    }
+
+    /// Switch to the file for the period containing `now`, if not already using it.
+    fn roll_to(&mut self, now: DateTime<Utc>) -> io::Result<()> {
+        let start = self.period_start(now);
+        if self.current == Some(start) {
+            return Ok(());
+        }
+
+        let previous = self.path();
+        self.file
+            .set_path(start.format(&self.pattern).to_string())?;
+        self.current = Some(start);
+
+        if let Some(previous) = previous.filter(|path| path.exists()) {
+            self.compressor.compress(previous);
+        }
+
+        self.prune(SystemTime::now())
+    }
+
+    /// Start of the period containing `now`, on the configured clock.
+    fn period_start(&self, now: DateTime<Utc>) -> NaiveDateTime {
+        let now = match self.clock {
+            RotationClock::Utc => now.naive_utc(),
+            RotationClock::Local => now.with_timezone(&Local).naive_local(),
+        };
+        let hour = now
+            .date()
+            .and_hms_opt(now.hour(), 0, 0)
+            .expect("hour of an existing time is valid");
+
+        match self.period {
+            RotationPeriod::Hourly => hour,
+            RotationPeriod::Daily => hour - Duration::hours(now.hour() as i64),
+            RotationPeriod::Weekly => {
+                let days = now.weekday().num_days_from_monday() as i64;
+                hour - Duration::hours(now.hour() as i64) - Duration::days(days)
+            }
+        }
+    }
+
+    /// Delete files matching the pattern older than the maximum age.
+    fn prune(&self, now: SystemTime) -> io::Result<()> {
+        let max_age = match self.max_age {
+            Some(max_age) => max_age,
+            None => return Ok(()),
+        };
+
+        let pattern = Path::new(&self.pattern);
+        let name_pattern = match pattern.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+        let dir = match pattern.parent().filter(|p| !p.as_os_str().is_empty()) {
+            Some(dir) => dir,
+            None => Path::new("."),
+        };
+
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+
+        for entry in entries {
+            let entry = entry?;
+            let path = entry.path();
+            if path == self.file.path() || !matches_pattern(&entry.file_name(), name_pattern) {
+                continue;
+            }
+
+            let modified = entry.metadata()?.modified()?;
+            let expired = now
+                .duration_since(modified)
+                .map(|age| age > max_age)
+                .unwrap_or(false);
+
+            if expired {
+                fs::remove_file(path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Path of the file currently being written to, if any.
+    pub fn path(&self) -> Option<PathBuf> {
+        self.current.map(|_| self.file.path().to_path_buf())
+    }
+}
+
+/// Check the pattern renders, and that only its file name changes from one period to the
+/// next, as pruning only looks in a single directory.
+fn check_pattern(pattern: &str) -> io::Result<()> {
+    crate::formatters::check_time_format(pattern)?;
+
+    let dir = Path::new(pattern).parent().and_then(|dir| dir.to_str());
+    if dir.is_some_and(|dir| dir.contains('%')) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "placeholders in the directory of `{}` are not supported",
+                pattern
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Whether a file name could have been produced by the `strftime` pattern.
+fn matches_pattern(name: &std::ffi::OsStr, pattern: &str) -> bool {
+    let name = match name.to_str() {
+        Some(name) => Compression::split_extension(name).0,
+        None => return false,
+    };
+
+    let mut parsed = chrono::format::Parsed::new();
+    chrono::format::parse(
+        &mut parsed,
+        name,
+        chrono::format::StrftimeItems::new(pattern),
+    )
+    .is_ok()
+}
+
+impl TacitOutput for TimedFileOutput {
+    fn close(&mut self) -> io::Result<()> {
+        self.compressor.wait();
+        self.file.flush()
+    }
+}
+
+impl Write for TimedFileOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // only switch files between lines, so a single entry never spans two files
+        if self.line_start || self.current.is_none() {
+            self.roll_to(Utc::now())?;
+        }
+
+        let written = self.file.write(buf)?;
+        if written > 0 {
+            self.line_start = buf[written - 1] == b'\n';
+        }
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tacit-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn period_boundaries() {
+        let now = Utc.with_ymd_and_hms(2021, 3, 31, 15, 49, 16).unwrap();
+        let start = |period| {
+            TimedFileOutput::default()
+                .with_period(period)
+                .period_start(now)
+                .to_string()
+        };
+
+        assert_eq!(start(RotationPeriod::Hourly), "2021-03-31 15:00:00");
+        assert_eq!(start(RotationPeriod::Daily), "2021-03-31 00:00:00");
+        assert_eq!(start(RotationPeriod::Weekly), "2021-03-29 00:00:00");
+    }
+
+    #[test]
+    fn switches_files_and_prunes_by_age() {
+        let dir = temp_dir("timed");
+        let pattern = dir.join("app-%Y-%m-%d.log");
+        fs::create_dir_all(&dir).unwrap();
+
+        let stale = dir.join("app-2000-01-01.log");
+        let unrelated = dir.join("other.log");
+        fs::write(&stale, "old\n").unwrap();
+        fs::write(&unrelated, "keep\n").unwrap();
+        let old = SystemTime::now() - std::time::Duration::from_secs(30 * 24 * 60 * 60);
+        fs::File::options()
+            .write(true)
+            .open(&stale)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        let mut output = TimedFileOutput::new(pattern.to_str().unwrap())
+            .unwrap()
+            .with_max_age(Some(std::time::Duration::from_secs(7 * 24 * 60 * 60)));
+
+        output
+            .roll_to(Utc.with_ymd_and_hms(2021, 3, 29, 23, 59, 0).unwrap())
+            .unwrap();
+        writeln!(output.file, "monday").unwrap();
+        output
+            .roll_to(Utc.with_ymd_and_hms(2021, 3, 30, 0, 0, 1).unwrap())
+            .unwrap();
+        writeln!(output.file, "tuesday").unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dir.join("app-2021-03-29.log")).unwrap(),
+            "monday\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("app-2021-03-30.log")).unwrap(),
+            "tuesday\n"
+        );
+        assert!(!stale.exists());
+        assert!(unrelated.exists());
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        for pattern in &["app-%Q.log", "app-%Y-%", "%Y/%m/app.log"] {
+            let err = TimedFileOutput::new(*pattern).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{}", pattern);
+        }
+    }
+}
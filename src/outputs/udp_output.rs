@@ -0,0 +1,432 @@
+//! # UDP Output
+//! Send every entry as its own datagram, for fire and forget logging where losing
+//! an entry is preferable to slowing the caller down.
+//!
+//! The socket is non blocking, entries that can not be sent immediately are
+//! dropped. Host names are looked up on a background thread, entries written
+//! before the lookup has finished are dropped too. Entries larger than the
+//! configured MTU are either truncated, ending with a ` truncated=true` marker,
+//! or split over several datagrams, each ending with a ` part=1/3` marker. Both
+//! markers can be changed to suit the formatter, e.g. to empty markers for
+//! entries that must not be altered. Entries are only cut between UTF-8
+//! characters.
+
+use super::TacitOutput;
+use std::{
+    io::{self, Write},
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    thread::{self, JoinHandle},
+};
+
+/// Default address used when no other is provided.
+const DEFAULT_ADDRESS: &str = "127.0.0.1:5170";
+
+/// Largest UDP payload fitting a 1500 byte Ethernet frame.
+const DEFAULT_MTU: usize = 1472;
+
+const DEFAULT_TRUNCATION_MARKER: &str = " truncated=true";
+
+const DEFAULT_PART_MARKER: &str = " part={index}/{count}";
+
+/// Room needed next to a marker for at least one UTF-8 character and a newline.
+const MIN_ROOM: usize = 5;
+
+/// What to do with entries larger than the MTU.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Oversize {
+    /// Cut the entry short, ending it with the truncation marker.
+    #[default]
+    Truncate,
+    /// Send the entry in parts, each ending with the part marker, e.g. ` part=2/3`.
+    Split,
+}
+
+pub struct UdpOutput {
+    address: String,
+    mtu: usize,
+    oversize: Oversize,
+    truncation_marker: String,
+    part_marker: String,
+    socket: Option<UdpSocket>,
+    lookup: Option<JoinHandle<io::Result<SocketAddr>>>,
+}
+
+impl Default for UdpOutput {
+    fn default() -> Self {
+        Self::new(DEFAULT_ADDRESS)
+    }
+}
+
+impl UdpOutput {
+    /// Send entries to `address`, e.g. `metrics.example.com:5170`.
+    pub fn new<S: Into<String>>(address: S) -> Self {
+        Self {
+            address: address.into(),
+            mtu: DEFAULT_MTU,
+            oversize: Oversize::default(),
+            truncation_marker: String::from(DEFAULT_TRUNCATION_MARKER),
+            part_marker: String::from(DEFAULT_PART_MARKER),
+            socket: None,
+            lookup: None,
+        }
+    }
+
+    /// Set the largest datagram payload, 1472 bytes by default. Fails with `InvalidInput`
+    /// if it leaves no room for entries next to the markers.
+    pub fn set_mtu(&mut self, mtu: usize) -> io::Result<()> {
+        check_room(mtu, &self.truncation_marker, &self.part_marker)?;
+        self.mtu = mtu;
+        Ok(())
+    }
+
+    /// Set the largest datagram payload, 1472 bytes by default. Fails with `InvalidInput`
+    /// if it leaves no room for entries next to the markers. Useful for chaining operations.
+    pub fn with_mtu(mut self, mtu: usize) -> io::Result<Self> {
+        self.set_mtu(mtu)?;
+        Ok(self)
+    }
+
+    /// Set what happens to entries larger than the MTU, truncated by default.
+    pub fn set_oversize(&mut self, oversize: Oversize) {
+        self.oversize = oversize;
+    }
+
+    /// Set what happens to entries larger than the MTU, truncated by default. Useful for
+    /// chaining operations.
+    #[must_use]
+    pub fn with_oversize(mut self, oversize: Oversize) -> Self {
+        self.set_oversize(oversize);
+        self
+    }
+
+    /// Set the marker ending truncated entries, ` truncated=true` by default. Fails with
+    /// `InvalidInput` if it leaves no room for entries within the MTU.
+    pub fn set_truncation_marker<S: Into<String>>(&mut self, marker: S) -> io::Result<()> {
+        let marker = marker.into();
+        check_room(self.mtu, &marker, &self.part_marker)?;
+        self.truncation_marker = marker;
+        Ok(())
+    }
+
+    /// Set the marker ending truncated entries, ` truncated=true` by default. Fails with
+    /// `InvalidInput` if it leaves no room for entries within the MTU. Useful for chaining
+    /// operations.
+    pub fn with_truncation_marker<S: Into<String>>(mut self, marker: S) -> io::Result<Self> {
+        self.set_truncation_marker(marker)?;
+        Ok(self)
+    }
+
+    /// Set the marker ending each part of a split entry, ` part={index}/{count}` by default.
+    /// `{index}` is replaced with the position of the part and `{count}` with the number of
+    /// parts. Fails with `InvalidInput` if it leaves no room for entries within the MTU.
+    pub fn set_part_marker<S: Into<String>>(&mut self, marker: S) -> io::Result<()> {
+        let marker = marker.into();
+        check_room(self.mtu, &self.truncation_marker, &marker)?;
+        self.part_marker = marker;
+        Ok(())
+    }
+
+    /// Set the marker ending each part of a split entry, ` part={index}/{count}` by default.
+    /// `{index}` is replaced with the position of the part and `{count}` with the number of
+    /// parts. Fails with `InvalidInput` if it leaves no room for entries within the MTU.
+    /// Useful for chaining operations.
+    pub fn with_part_marker<S: Into<String>>(mut self, marker: S) -> io::Result<Self> {
+        self.set_part_marker(marker)?;
+        Ok(self)
+    }
+
+    /// Address to send to, or `None` while a host name is still being looked up in the
+    /// background.
+    fn resolve(&mut self) -> io::Result<Option<SocketAddr>> {
+        if let Ok(address) = self.address.parse() {
+            return Ok(Some(address));
+        }
+
+        match self.lookup.take() {
+            Some(lookup) if !lookup.is_finished() => {
+                self.lookup = Some(lookup);
+                Ok(None)
+            }
+            Some(lookup) => match lookup.join() {
+                Ok(address) => address.map(Some),
+                Err(_) => Err(io::Error::other("looking up the address panicked")),
+            },
+            None => {
+                let address = self.address.clone();
+                let lookup = thread::Builder::new()
+                    .name(String::from("tacit-udp"))
+                    .spawn(move || {
+                        address
+                            .to_socket_addrs()?
+                            .next()
+                            .ok_or_else(|| io::Error::from(io::ErrorKind::AddrNotAvailable))
+                    })?;
+                self.lookup = Some(lookup);
+                Ok(None)
+            }
+        }
+    }
+
+    fn connect(address: SocketAddr) -> io::Result<UdpSocket> {
+        let local = if address.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(address)?;
+        socket.set_nonblocking(true)?;
+        Ok(socket)
+    }
+
+    /// Datagrams for an entry, cut to fit the MTU. Fails with `InvalidInput` if the entry
+    /// needs so many parts that their markers leave no room within the MTU.
+    fn datagrams(&self, entry: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+        if entry.len() <= self.mtu {
+            return Ok(vec![entry.to_vec()]);
+        }
+
+        let (body, newline) = match entry.strip_suffix(b"\n") {
+            Some(body) => (body, &b"\n"[..]),
+            None => (entry, &b""[..]),
+        };
+
+        match self.oversize {
+            Oversize::Truncate => {
+                let room = self
+                    .mtu
+                    .saturating_sub(self.truncation_marker.len() + newline.len());
+                let mut datagram = body[..char_boundary(body, room)].to_vec();
+                datagram.extend_from_slice(self.truncation_marker.as_bytes());
+                datagram.extend_from_slice(newline);
+                Ok(vec![datagram])
+            }
+            Oversize::Split => {
+                // leave room for the largest marker this entry could need
+                let marker = part_marker(&self.part_marker, body.len(), body.len());
+                let room = self.mtu.saturating_sub(marker.len() + newline.len());
+                if room < MIN_ROOM - 1 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "entry needs too many parts to fit their markers within the MTU",
+                    ));
+                }
+
+                let mut parts = Vec::new();
+                let mut rest = body;
+                while !rest.is_empty() {
+                    let end = match char_boundary(rest, room) {
+                        0 => rest.len().min(room),
+                        end => end,
+                    };
+                    parts.push(&rest[..end]);
+                    rest = &rest[end..];
+                }
+
+                let count = parts.len();
+                Ok(parts
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, part)| {
+                        let mut datagram = part.to_vec();
+                        datagram.extend_from_slice(
+                            part_marker(&self.part_marker, index + 1, count).as_bytes(),
+                        );
+                        datagram.extend_from_slice(newline);
+                        datagram
+                    })
+                    .collect())
+            }
+        }
+    }
+}
+
+/// Fail with `InvalidInput` unless the MTU leaves room for entries next to either marker.
+fn check_room(mtu: usize, truncation_marker: &str, part_marker: &str) -> io::Result<()> {
+    let marker = truncation_marker
+        .len()
+        .max(self::part_marker(part_marker, 1, 1).len());
+    if mtu < marker + MIN_ROOM {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "an MTU of {} bytes leaves no room for entries next to a {} byte marker",
+                mtu, marker
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// The part marker for the part at `index` out of `count`.
+fn part_marker(marker: &str, index: usize, count: usize) -> String {
+    marker
+        .replace("{index}", &index.to_string())
+        .replace("{count}", &count.to_string())
+}
+
+/// Largest index at or before `max` that does not fall within a UTF-8 character.
+fn char_boundary(bytes: &[u8], max: usize) -> usize {
+    if max >= bytes.len() {
+        return bytes.len();
+    }
+    let mut end = max;
+    while end > 0 && (bytes[end] & 0b1100_0000) == 0b1000_0000 {
+        end -= 1;
+    }
+    end
+}
+
+impl TacitOutput for UdpOutput {}
+
+impl Write for UdpOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.socket.is_none() {
+            match self.resolve()? {
+                Some(address) => self.socket = Some(Self::connect(address)?),
+                None => return Ok(buf.len()),
+            }
+        }
+
+        for datagram in self.datagrams(buf)? {
+            let result = match &self.socket {
+                Some(socket) => socket.send(&datagram),
+                None => break,
+            };
+            match result {
+                Ok(_) => {}
+                // nobody listening, or no room to send right now, drop the entry
+                Err(err)
+                    if err.kind() == io::ErrorKind::WouldBlock
+                        || err.kind() == io::ErrorKind::ConnectionRefused =>
+                {
+                    break
+                }
+                Err(err) => {
+                    self.socket = None;
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receive(server: &UdpSocket, count: usize) -> Vec<String> {
+        let mut buf = [0; 2048];
+        (0..count)
+            .map(|_| {
+                let len = server.recv(&mut buf).unwrap();
+                String::from_utf8(buf[..len].to_vec()).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sends_and_truncates_entries() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut output = UdpOutput::new(server.local_addr().unwrap().to_string())
+            .with_mtu(20)
+            .unwrap();
+
+        output.write_all(b"msg=short\n").unwrap();
+        output.write_all("msg=ééééééééé\n".as_bytes()).unwrap();
+
+        assert_eq!(
+            receive(&server, 2),
+            vec!["msg=short\n", "msg= truncated=true\n"]
+        );
+    }
+
+    #[test]
+    fn splits_entries_with_part_markers() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut output = UdpOutput::new(server.local_addr().unwrap().to_string())
+            .with_mtu(24)
+            .unwrap()
+            .with_oversize(Oversize::Split);
+
+        output
+            .write_all(b"msg=abcdefghijklmnopqrstuvwxyz\n")
+            .unwrap();
+
+        let parts = receive(&server, 3);
+        assert!(parts.iter().all(|part| part.len() <= 24));
+        assert_eq!(parts[0], "msg=abcdefgh part=1/3\n");
+        assert_eq!(parts[2], "uvwxyz part=3/3\n");
+        let joined: String = parts
+            .iter()
+            .map(|part| part.rsplit_once(" part=").unwrap().0)
+            .collect();
+        assert_eq!(joined, "msg=abcdefghijklmnopqrstuvwxyz");
+    }
+
+    #[test]
+    fn uses_custom_part_markers() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut output = UdpOutput::new(server.local_addr().unwrap().to_string())
+            .with_oversize(Oversize::Split)
+            .with_part_marker("")
+            .unwrap()
+            .with_truncation_marker("")
+            .unwrap()
+            .with_mtu(16)
+            .unwrap();
+
+        output.write_all(b"{\"msg\":\"abcdefghijkl\"}\n").unwrap();
+
+        assert_eq!(
+            receive(&server, 2),
+            vec!["{\"msg\":\"abcdefg\n", "hijkl\"}\n"]
+        );
+    }
+
+    #[test]
+    fn rejects_mtus_without_room_for_markers() {
+        let kind = |output: io::Result<UdpOutput>| output.err().map(|err| err.kind());
+
+        assert_eq!(
+            kind(UdpOutput::default().with_mtu(19)),
+            Some(io::ErrorKind::InvalidInput)
+        );
+        assert_eq!(kind(UdpOutput::default().with_mtu(20)), None);
+        assert_eq!(
+            kind(
+                UdpOutput::default()
+                    .with_mtu(20)
+                    .and_then(|output| output.with_truncation_marker(" truncated=true!"))
+            ),
+            Some(io::ErrorKind::InvalidInput)
+        );
+    }
+
+    #[test]
+    fn looks_up_host_names_in_the_background() {
+        let local = ("localhost", 0).to_socket_addrs().unwrap().next().unwrap();
+        let server = UdpSocket::bind(local).unwrap();
+        let port = server.local_addr().unwrap().port();
+        let mut output = UdpOutput::new(format!("localhost:{}", port));
+
+        // dropped while the lookup is running
+        output.write_all(b"msg=early\n").unwrap();
+        while !output
+            .lookup
+            .as_ref()
+            .is_some_and(|lookup| lookup.is_finished())
+        {
+            thread::sleep(std::time::Duration::from_millis(1));
+        }
+        output.write_all(b"msg=late\n").unwrap();
+
+        assert_eq!(receive(&server, 1), vec!["msg=late\n"]);
+    }
+}
@@ -5,6 +5,7 @@
 use log::Record;
 
 /// Property to add to the log output
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum StaticProperty {
     String(String),
     Number(i64),
@@ -51,3 +52,13 @@ pub enum Property {
     /// Represent a dynamic function that returns a StaticProperty
     Function(Box<dyn Fn(&Record) -> StaticProperty + Send + Sync>),
 }
+
+impl Property {
+    /// Value of the property for a record.
+    pub(crate) fn value(&self, record: &Record) -> StaticProperty {
+        match self {
+            Self::Static(value) => value.clone(),
+            Self::Function(f) => f(record),
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! # Test Utilities
+//! Test doubles shared by the unit tests of several modules.
+
+use crate::TacitOutput;
+use parking_lot::Mutex;
+use std::{
+    io::{self, Write},
+    sync::Arc,
+};
+
+#[cfg(feature = "http")]
+pub(crate) use http::*;
+
+/// An output collecting everything written to it, shared between its clones so tests can
+/// inspect what a logger wrote.
+#[derive(Clone, Default)]
+pub(crate) struct SharedOutput {
+    written: Arc<Mutex<Vec<u8>>>,
+    flushes: Arc<Mutex<usize>>,
+}
+
+impl SharedOutput {
+    /// Everything written so far.
+    pub(crate) fn contents(&self) -> String {
+        String::from_utf8(self.written.lock().clone()).unwrap()
+    }
+
+    /// Number of times the output was flushed.
+    pub(crate) fn flushes(&self) -> usize {
+        *self.flushes.lock()
+    }
+}
+
+impl TacitOutput for SharedOutput {}
+
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.written.lock().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        *self.flushes.lock() += 1;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "http")]
+mod http {
+    use parking_lot::Mutex;
+    use std::{
+        collections::VecDeque,
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        sync::{
+            mpsc::{self, Receiver},
+            Arc,
+        },
+        thread,
+    };
+
+    /// A request received by the stand-in endpoint.
+    pub(crate) struct Request {
+        pub(crate) path: String,
+        pub(crate) headers: Vec<(String, String)>,
+        pub(crate) body: Vec<u8>,
+    }
+
+    impl Request {
+        pub(crate) fn header(&self, name: &str) -> Option<&str> {
+            self.headers
+                .iter()
+                .find(|(header, _)| header.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        }
+
+        pub(crate) fn text(&self) -> String {
+            String::from_utf8(self.body.clone()).unwrap()
+        }
+    }
+
+    /// A stand-in HTTP endpoint answering requests with `statuses`, then with 200. Returns
+    /// its base URL, e.g. `http://127.0.0.1:41234`, and the requests it receives.
+    pub(crate) fn serve(statuses: Vec<u16>) -> (String, Receiver<Request>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let statuses = Arc::new(Mutex::new(VecDeque::from(statuses)));
+        let (requests, received) = mpsc::channel();
+
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let statuses = statuses.clone();
+                let requests = requests.clone();
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut stream = stream;
+                    loop {
+                        let mut line = String::new();
+                        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                            return;
+                        }
+                        let path = line.split(' ').nth(1).unwrap_or_default().to_string();
+                        let mut headers = Vec::new();
+                        loop {
+                            line.clear();
+                            reader.read_line(&mut line).unwrap();
+                            match line.trim_end().split_once(": ") {
+                                Some((name, value)) => {
+                                    headers.push((name.to_string(), value.to_string()))
+                                }
+                                None => break,
+                            }
+                        }
+                        let len = headers
+                            .iter()
+                            .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                            .map(|(_, value)| value.parse().unwrap())
+                            .unwrap_or(0);
+                        let mut body = vec![0; len];
+                        reader.read_exact(&mut body).unwrap();
+
+                        let status = statuses.lock().pop_front().unwrap_or(200);
+                        let _ = requests.send(Request {
+                            path,
+                            headers,
+                            body,
+                        });
+                        write!(
+                            stream,
+                            "HTTP/1.1 {} Status\r\nContent-Length: 0\r\n\r\n",
+                            status
+                        )
+                        .unwrap();
+                    }
+                });
+            }
+        });
+
+        (url, received)
+    }
+}
@@ -0,0 +1,270 @@
+//! # Async Writer
+//! Hands formatted entries off to a dedicated thread that owns the writing to an
+//! output, so logging never blocks the caller on stdout or disk I/O.
+//!
+//! When entries are produced faster than they can be written the queue fills
+//! up, at which point the configured `Backpressure` decides what happens to new
+//! entries. Dropped entries are counted, and the count is reported in the log
+//! itself at a configurable interval, as well as when the logger is flushed or
+//! shut down. Entries logged by the writer thread itself,
+//! e.g. by an output, are dropped rather than waited for while the queue is full.
+
+use crate::{error_policy::ErrorHandler, RecordInfo, TacitOutput};
+use log::Level;
+use parking_lot::{Condvar, Mutex};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle, ThreadId},
+    time::{Duration, Instant},
+};
+
+/// What to do with new entries while the queue is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Wait for room in the queue, nothing is lost but the caller may block.
+    #[default]
+    Block,
+    /// Drop the entry being logged.
+    DropNewest,
+    /// Drop the oldest queued entry to make room.
+    DropOldest,
+    /// Drop entries less severe than the given level, wait for room for the rest.
+    /// `DropBelow(Level::Warn)` keeps `Error` and `Warn` while dropping everything else.
+    DropBelow(Level),
+}
+
+/// Number of entries a logger has dropped due to `Backpressure`.
+#[derive(Clone, Default)]
+pub struct DropCounter(Arc<AtomicU64>);
+
+impl DropCounter {
+    /// Total entries dropped so far.
+    pub fn count(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn increment(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+struct State {
+    entries: VecDeque<(RecordInfo, Vec<u8>)>,
+    writing: bool,
+    closed: bool,
+}
+
+/// Bounded queue shared between loggers and the writer thread.
+struct Queue {
+    state: Mutex<State>,
+    capacity: usize,
+    not_empty: Condvar,
+    not_full: Condvar,
+    drained: Condvar,
+}
+
+pub(crate) struct AsyncWriter {
+    queue: Arc<Queue>,
+    thread: Mutex<Option<JoinHandle<()>>>,
+    thread_id: ThreadId,
+    backpressure: Backpressure,
+    dropped: DropCounter,
+    report: Mutex<Report>,
+}
+
+/// Tracks when dropped entries were last reported.
+struct Report {
+    interval: Duration,
+    at: Instant,
+    count: u64,
+}
+
+impl AsyncWriter {
+    /// Spawn the writer thread for `output`, queueing at most `capacity` entries.
+    pub(crate) fn new<O: 'static + TacitOutput>(
+        output: Arc<Mutex<O>>,
+        capacity: usize,
+        backpressure: Backpressure,
+        dropped: DropCounter,
+        report_interval: Duration,
+        errors: Arc<ErrorHandler>,
+    ) -> Self {
+        let queue = Arc::new(Queue {
+            state: Mutex::new(State {
+                entries: VecDeque::with_capacity(capacity),
+                writing: false,
+                closed: false,
+            }),
+            capacity: capacity.max(1),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            drained: Condvar::new(),
+        });
+
+        let thread = {
+            let queue = queue.clone();
+            thread::Builder::new()
+                .name(String::from("tacit-writer"))
+                .spawn(move || run(&queue, &output, &errors))
+                .expect("Unable to spawn logger writer thread")
+        };
+
+        Self {
+            queue,
+            thread_id: thread.thread().id(),
+            thread: Mutex::new(Some(thread)),
+            backpressure,
+            report: Mutex::new(Report {
+                interval: report_interval,
+                at: Instant::now(),
+                count: dropped.count(),
+            }),
+            dropped,
+        }
+    }
+
+    /// Queue an entry for writing, applying the backpressure policy if the queue is full.
+    /// Once the writer has been shut down the entry is handed back to be written directly.
+    pub(crate) fn send(
+        &self,
+        info: RecordInfo,
+        entry: Vec<u8>,
+    ) -> Result<(), Box<(RecordInfo, Vec<u8>)>> {
+        let mut state = self.queue.state.lock();
+        if state.closed {
+            return Err(Box::new((info, entry)));
+        }
+
+        if state.entries.len() >= self.queue.capacity {
+            match self.backpressure {
+                Backpressure::DropNewest => {
+                    self.dropped.increment();
+                    return Ok(());
+                }
+                Backpressure::DropOldest => {
+                    state.entries.pop_front();
+                    self.dropped.increment();
+                }
+                Backpressure::DropBelow(threshold) if info.level() > threshold => {
+                    self.dropped.increment();
+                    return Ok(());
+                }
+                Backpressure::DropBelow(_) | Backpressure::Block => {}
+            }
+        }
+
+        // the writer thread would be waiting for itself to make room
+        if state.entries.len() >= self.queue.capacity && self.on_writer_thread() {
+            self.dropped.increment();
+            return Ok(());
+        }
+
+        while state.entries.len() >= self.queue.capacity && !state.closed {
+            self.queue.not_full.wait(&mut state);
+        }
+        if state.closed {
+            return Err(Box::new((info, entry)));
+        }
+
+        state.entries.push_back((info, entry));
+        self.queue.not_empty.notify_one();
+        Ok(())
+    }
+
+    /// Block until every entry queued so far has been written and the output flushed.
+    pub(crate) fn flush(&self) {
+        if self.on_writer_thread() {
+            return;
+        }
+
+        let mut state = self.queue.state.lock();
+        while (!state.entries.is_empty() || state.writing) && !state.closed {
+            self.queue.drained.wait(&mut state);
+        }
+    }
+
+    /// Stop accepting entries, and wait for the writer thread to finish writing those
+    /// already queued.
+    pub(crate) fn shutdown(&self) {
+        self.queue.state.lock().closed = true;
+        self.queue.not_empty.notify_all();
+        self.queue.not_full.notify_all();
+        self.queue.drained.notify_all();
+
+        if let Some(thread) = self.thread.lock().take() {
+            let _ = thread.join();
+        }
+    }
+
+    fn on_writer_thread(&self) -> bool {
+        thread::current().id() == self.thread_id
+    }
+
+    /// Whether the queue is currently empty.
+    #[cfg(test)]
+    pub(crate) fn is_idle(&self) -> bool {
+        self.queue.state.lock().entries.is_empty()
+    }
+
+    /// Number of entries dropped since the last report, once the report interval has passed
+    /// or right away when `now` is set.
+    pub(crate) fn due_report(&self, now: bool) -> Option<u64> {
+        let count = self.dropped.count();
+        let mut report = self.report.lock();
+
+        if count == report.count || (!now && report.at.elapsed() < report.interval) {
+            return None;
+        }
+
+        let dropped = count - report.count;
+        report.count = count;
+        report.at = Instant::now();
+        Some(dropped)
+    }
+}
+
+impl Drop for AsyncWriter {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Body of the writer thread, writes entries until the queue is closed and drained.
+fn run<O: TacitOutput>(queue: &Queue, output: &Mutex<O>, errors: &ErrorHandler) {
+    loop {
+        let mut batch = {
+            let mut state = queue.state.lock();
+            while state.entries.is_empty() && !state.closed {
+                queue.not_empty.wait(&mut state);
+            }
+            if state.entries.is_empty() {
+                return;
+            }
+            state.writing = true;
+            queue.not_full.notify_all();
+            std::mem::take(&mut state.entries)
+        };
+
+        {
+            let mut output = output.lock();
+            for (info, entry) in batch.drain(..) {
+                if let Err(err) = output.write_entry(&info, &entry) {
+                    errors.handle(&err, Some(&entry));
+                }
+            }
+            if let Err(err) = output.flush() {
+                errors.handle(&err, None);
+            }
+        }
+
+        let mut state = queue.state.lock();
+        state.writing = false;
+        if state.entries.is_empty() {
+            queue.drained.notify_all();
+        }
+    }
+}